@@ -0,0 +1,520 @@
+//! Data-quality checks of the NPU XML source.
+//!
+//! Errors are problems that make the conversion lose or misrepresent data;
+//! warnings are suspicious but may be legitimate, e.g. a `replaced_by` code
+//! that is missing because the input is an extract of the full release.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+use time::{Duration, UtcDateTime};
+
+use crate::code_system::remove_duplicate_spaces;
+use crate::definition;
+use crate::npu_concepts::{Npubegreb, Npubegreber, parse_date, split_codes};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Name of the check that produced the finding.
+    pub check: &'static str,
+    pub npu_code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub records: usize,
+    pub codes: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    fn add(
+        &mut self,
+        severity: Severity,
+        check: &'static str,
+        npu_code: &str,
+        field: Option<&'static str>,
+        message: String,
+    ) {
+        match severity {
+            Severity::Error => self.errors += 1,
+            Severity::Warning => self.warnings += 1,
+        }
+        self.findings.push(Finding {
+            severity,
+            check,
+            npu_code: npu_code.to_owned(),
+            field,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            write!(
+                f,
+                "{}[{}] {}",
+                finding.severity, finding.check, finding.npu_code
+            )?;
+            if let Some(field) = finding.field {
+                write!(f, " {field}")?;
+            }
+            writeln!(f, ": {}", finding.message)?;
+        }
+        writeln!(
+            f,
+            "{} records, {} codes: {} errors, {} warnings",
+            self.records, self.codes, self.errors, self.warnings
+        )
+    }
+}
+
+/// Run all checks over `data`.
+pub fn check(data: &Npubegreber) -> Report {
+    let mut versions = HashMap::<&str, Vec<&Npubegreb>>::new();
+    let mut codes = Vec::new();
+    for npubegreb in &data.npubegreb {
+        let rows = versions.entry(npubegreb.npu_code.as_str()).or_default();
+        if rows.is_empty() {
+            codes.push(npubegreb.npu_code.as_str());
+        }
+        rows.push(npubegreb);
+    }
+
+    let mut report = Report {
+        records: data.npubegreb.len(),
+        codes: codes.len(),
+        errors: 0,
+        warnings: 0,
+        findings: Vec::new(),
+    };
+
+    for npubegreb in &data.npubegreb {
+        check_values(&mut report, npubegreb);
+        check_dates(&mut report, npubegreb);
+        check_definitions(&mut report, npubegreb);
+    }
+    for code in &codes {
+        check_current_versions(&mut report, code, &versions[code]);
+        check_periods(&mut report, code, &versions[code]);
+    }
+    check_replacements(&mut report, &codes, &versions);
+
+    report
+}
+
+fn check_values(report: &mut Report, npubegreb: &Npubegreb) {
+    for (field, value, expected) in [
+        ("active", &npubegreb.active, &["0", "1"][..]),
+        (
+            "current_version",
+            &npubegreb.current_version,
+            &["true", "false"][..],
+        ),
+        (
+            "context_dependent",
+            &npubegreb.context_dependent,
+            &["", "Ja", "Nej"][..],
+        ),
+    ] {
+        if !expected.contains(&value.as_str()) {
+            report.add(
+                Severity::Error,
+                "unexpected_value",
+                &npubegreb.npu_code,
+                Some(field),
+                format!("unexpected value {value:?}, expected one of {expected:?}"),
+            );
+        }
+    }
+}
+
+fn check_dates(report: &mut Report, npubegreb: &Npubegreb) {
+    for (field, value, required) in [
+        ("created_date", &npubegreb.created_date, true),
+        ("change_date", &npubegreb.change_date, true),
+        ("effective_from", &npubegreb.effective_from, true),
+        ("effective_to", &npubegreb.effective_to, false),
+    ] {
+        if value.is_empty() {
+            if required {
+                report.add(
+                    Severity::Warning,
+                    "missing_date",
+                    &npubegreb.npu_code,
+                    Some(field),
+                    "date is empty".to_owned(),
+                );
+            }
+        } else if let Err(e) = parse_date(value) {
+            report.add(
+                Severity::Error,
+                "unparseable_date",
+                &npubegreb.npu_code,
+                Some(field),
+                format!("cannot parse {value:?}: {e}"),
+            );
+        }
+    }
+}
+
+/// The axes of the short and full definitions against the separate axis
+/// fields, which the NPU release duplicates.
+fn check_definitions(report: &mut Report, npubegreb: &Npubegreb) {
+    let short_axes = [
+        (
+            ("system_short", &npubegreb.system_short),
+            ("sys_spec_short", &npubegreb.sys_spec_short),
+        ),
+        (
+            ("component_short", &npubegreb.component_short),
+            ("comp_spec_short", &npubegreb.comp_spec_short),
+        ),
+        (
+            ("kind_of_property_short", &npubegreb.kind_of_property_short),
+            ("proc_short", &npubegreb.proc_short),
+        ),
+    ];
+    let full_axes = [
+        (
+            ("system", &npubegreb.system),
+            ("sys_spec", &npubegreb.sys_spec),
+        ),
+        (
+            ("component", &npubegreb.component),
+            ("comp_spec", &npubegreb.comp_spec),
+        ),
+        (
+            ("kind_of_property", &npubegreb.kind_of_property),
+            ("proc", &npubegreb.proc),
+        ),
+    ];
+    for (field, text, axes, unit) in [
+        (
+            "short_definition",
+            &npubegreb.short_definition,
+            short_axes,
+            ("unit_short", &npubegreb.unit_short),
+        ),
+        (
+            "full_definition",
+            &npubegreb.full_definition,
+            full_axes,
+            ("unit", &npubegreb.unit),
+        ),
+    ] {
+        let text = remove_duplicate_spaces(text);
+        let definition = match definition::parse(&text) {
+            Ok(definition) => definition,
+            Err(e) => {
+                report.add(
+                    Severity::Warning,
+                    "definition_syntax",
+                    &npubegreb.npu_code,
+                    Some(field),
+                    format!("cannot parse {text:?}: {e}"),
+                );
+                continue;
+            }
+        };
+        let mut mismatch = |parsed: &str, (axis_field, expected): (&str, &str)| {
+            if definition::normalize(parsed) != definition::normalize(expected) {
+                report.add(
+                    Severity::Warning,
+                    "definition_mismatch",
+                    &npubegreb.npu_code,
+                    Some(field),
+                    format!("has {parsed:?} where {axis_field} is {:?}", expected.trim()),
+                );
+            }
+        };
+
+        let parsed = [
+            definition.system,
+            definition.component,
+            definition.kind_of_property,
+        ];
+        for (axis, ((name_field, name), (specification_field, specification))) in
+            parsed.iter().zip(axes)
+        {
+            // A component such as `Fibrinopeptide B[beta](1-42)` ends in
+            // parentheses without having a specification, so the parts are
+            // only compared if the whole axis differs.
+            let expected = definition::axis_text(name, specification);
+            if definition::normalize(axis.text) == definition::normalize(&expected) {
+                continue;
+            }
+            if definition::normalize(axis.name) != definition::normalize(name) {
+                mismatch(axis.name, (name_field, name));
+            } else {
+                mismatch(
+                    axis.specification.unwrap_or_default(),
+                    (specification_field, specification),
+                );
+            }
+        }
+        mismatch(definition.unit, (unit.0, unit.1));
+    }
+}
+
+fn check_current_versions(report: &mut Report, code: &str, rows: &[&Npubegreb]) {
+    let current = rows
+        .iter()
+        .filter(|npubegreb| npubegreb.is_current())
+        .count();
+    if current > 1 {
+        report.add(
+            Severity::Error,
+            "duplicate_current_version",
+            code,
+            Some("current_version"),
+            format!("{current} rows have current_version=true"),
+        );
+    } else if current == 0 {
+        report.add(
+            Severity::Warning,
+            "no_current_version",
+            code,
+            Some("current_version"),
+            "no row has current_version=true, the code is not converted".to_owned(),
+        );
+    }
+}
+
+/// Overlaps and gaps between the effective periods of the versions of a code.
+/// Versions follow each other with a one minute resolution, e.g. an
+/// `effective_to` of 16:18 followed by an `effective_from` of 16:19.
+fn check_periods(report: &mut Report, code: &str, rows: &[&Npubegreb]) {
+    let mut periods: Vec<(UtcDateTime, &str, Option<UtcDateTime>, &Npubegreb)> = rows
+        .iter()
+        .filter_map(|npubegreb| {
+            let (from, from_text) = version_start(npubegreb)?;
+            let to = parse_date(&npubegreb.effective_to).ok();
+            Some((from, from_text, to, *npubegreb))
+        })
+        .collect();
+    periods.sort_by_key(|(from, _, _, _)| *from);
+
+    for pair in periods.windows(2) {
+        let (_, previous_from, previous_to, previous) = pair[0];
+        let (next_from, next_from_text, _, _) = pair[1];
+        match previous_to {
+            None => report.add(
+                Severity::Warning,
+                "overlapping_periods",
+                code,
+                Some("effective_to"),
+                format!(
+                    "version effective from {previous_from} has no end but is followed by a version effective from {next_from_text}",
+                ),
+            ),
+            Some(previous_to) if next_from <= previous_to => report.add(
+                Severity::Warning,
+                "overlapping_periods",
+                code,
+                Some("effective_from"),
+                format!(
+                    "version effective from {next_from_text} starts before the previous version ends at {}",
+                    previous.effective_to
+                ),
+            ),
+            Some(previous_to) if next_from - previous_to > Duration::minutes(1) => report.add(
+                Severity::Warning,
+                "gap_in_periods",
+                code,
+                Some("effective_from"),
+                format!(
+                    "no version is effective between {} and {next_from_text}",
+                    previous.effective_to
+                ),
+            ),
+            Some(_) => {}
+        }
+    }
+}
+
+/// The start of the period of a version, as a date and as written. NPU keeps
+/// the original `effective_from` of a concept on its later versions, so a
+/// version starts at its `change_date` if that is later.
+fn version_start(npubegreb: &Npubegreb) -> Option<(UtcDateTime, &str)> {
+    let from = parse_date(&npubegreb.effective_from).ok()?;
+    Some(match parse_date(&npubegreb.change_date) {
+        Ok(changed) if changed > from => (changed, npubegreb.change_date.trim()),
+        _ => (from, npubegreb.effective_from.trim()),
+    })
+}
+
+/// Unknown `replaced_by` targets and cycles in the replacements of the
+/// current versions.
+fn check_replacements(
+    report: &mut Report,
+    codes: &[&str],
+    versions: &HashMap<&str, Vec<&Npubegreb>>,
+) {
+    let mut replaced_by = HashMap::<&str, Vec<&str>>::new();
+    for code in codes {
+        for npubegreb in &versions[code] {
+            if !npubegreb.is_current() {
+                continue;
+            }
+            for replacement in split_codes(&npubegreb.replaced_by) {
+                if !versions.contains_key(replacement) {
+                    report.add(
+                        Severity::Warning,
+                        "unknown_replacement",
+                        code,
+                        Some("replaced_by"),
+                        format!("replaced by {replacement} which is not in the input"),
+                    );
+                }
+                replaced_by.entry(code).or_default().push(replacement);
+            }
+        }
+    }
+
+    // Depth-first search; a code that is reached again while still on the
+    // path closes a cycle, which is reported once from its first code.
+    let mut done = HashSet::<&str>::new();
+    for code in codes {
+        let mut path = Vec::<&str>::new();
+        let mut stack = vec![(*code, 0)];
+        while let Some((current, depth)) = stack.pop() {
+            path.truncate(depth);
+            if let Some(start) = path.iter().position(|&c| c == current) {
+                let cycle = path[start..].join(" -> ");
+                report.add(
+                    Severity::Error,
+                    "replacement_cycle",
+                    path[start],
+                    Some("replaced_by"),
+                    format!("replacement cycle {cycle} -> {current}"),
+                );
+                continue;
+            }
+            if !done.insert(current) {
+                continue;
+            }
+            path.push(current);
+            for next in replaced_by.get(current).into_iter().flatten() {
+                stack.push((next, depth + 1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    fn findings<'a>(report: &'a Report, check: &str) -> Vec<&'a str> {
+        report
+            .findings
+            .iter()
+            .filter(|finding| finding.check == check)
+            .map(|finding| finding.npu_code.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn later_version_starts_at_its_change_date() {
+        // NPU01013 keeps its effective_from of 1997 on the version changed in
+        // 2016.
+        let report = check(&test_data());
+        assert_eq!(findings(&report, "overlapping_periods"), Vec::<&str>::new());
+        assert_eq!(findings(&report, "gap_in_periods"), Vec::<&str>::new());
+    }
+
+    /// The test data with the current versions of each code replaced by the
+    /// codes given for it.
+    fn with_replacements(replacements: &[(&str, &str)]) -> Npubegreber {
+        let mut data = test_data();
+        for npubegreb in &mut data.npubegreb {
+            if let Some((_, replaced_by)) = replacements
+                .iter()
+                .find(|(code, _)| *code == npubegreb.npu_code)
+                && npubegreb.is_current()
+            {
+                npubegreb.replaced_by = (*replaced_by).to_owned();
+            }
+        }
+        data
+    }
+
+    fn messages<'a>(report: &'a Report, check: &str) -> Vec<&'a str> {
+        report
+            .findings
+            .iter()
+            .filter(|finding| finding.check == check)
+            .map(|finding| finding.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn replacement_cycles_are_reported_once() {
+        let report = check(&with_replacements(&[
+            ("NPU01001", "NPU01002"),
+            ("NPU01002", "NPU01003"),
+            ("NPU01003", "NPU01001"),
+            ("NPU01007", "NPU01007"),
+        ]));
+        assert_eq!(
+            messages(&report, "replacement_cycle"),
+            [
+                "replacement cycle NPU01001 -> NPU01002 -> NPU01003 -> NPU01001",
+                "replacement cycle NPU01007 -> NPU01007",
+            ]
+        );
+        assert_eq!(
+            findings(&report, "replacement_cycle"),
+            ["NPU01001", "NPU01007"]
+        );
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn shared_replacements_are_no_cycle() {
+        // NPU01001 reaches NPU01003 both through NPU01002 and through NPU01007.
+        let report = check(&with_replacements(&[
+            ("NPU01001", "NPU01002, NPU01007"),
+            ("NPU01002", "NPU01003"),
+            ("NPU01007", "NPU01003"),
+            ("NPU01008", "NPU99999"),
+        ]));
+        assert!(findings(&report, "replacement_cycle").is_empty());
+        // NPU01012 is replaced by NPU10504 in the test data.
+        assert_eq!(
+            findings(&report, "unknown_replacement"),
+            ["NPU01008", "NPU01012"]
+        );
+    }
+}
@@ -0,0 +1,600 @@
+use time::UtcDateTime;
+use time::UtcOffset;
+use time::macros::format_description;
+
+use crate::error::{BuildContext, Error};
+use crate::narrative::{self, Narrative};
+use crate::npu_concepts::{Npubegreber, parse_date};
+
+use fhir_sdk::r5::resources::CodeSystem;
+use fhir_sdk::r5::*;
+
+/// The canonical URL of the NPU code system.
+pub const URL: &str = "http://npu-terminology.org";
+/// The OID of the NPU code system, used in CDA and HL7 v3 messages.
+pub const OID: &str = "1.2.208.176.9.1";
+/// The NPU release the CodeSystem is generated for.
+pub const VERSION: &str = "INT 2025-05-28";
+/// The HL7 v2 coding system identifier of NPU, from HL7 table 0396.
+pub const V2_IDENTIFIER: &str = "NPU";
+/// The publisher of the NPU code system.
+pub const PUBLISHER: &str = "Danish Health Data Authority";
+
+/// A field value of the NPU XML that the conversion discarded or coerced.
+pub struct Loss {
+    pub npu_code: String,
+    pub field: String,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl Loss {
+    pub fn new(npu_code: &str, field: &str, value: &str, reason: &'static str) -> Self {
+        Loss {
+            npu_code: npu_code.to_owned(),
+            field: field.to_owned(),
+            value: value.to_owned(),
+            reason,
+        }
+    }
+}
+
+/// Which concepts the CodeSystem contains, i.e. its `content` mode.
+pub enum Content {
+    /// All current concepts of the data.
+    Complete,
+    /// No concepts, for servers that have NPU loaded already.
+    NotPresent,
+    /// The first current concepts of the data, at most this many.
+    Example(usize),
+    /// The current concepts of the data, which were selected by `criteria`.
+    Fragment { criteria: String },
+}
+
+/// The number of concepts the complete code system has for `data`.
+pub fn concept_count(data: &Npubegreber) -> u32 {
+    data.current().count() as u32
+}
+
+/// Build the NPU CodeSystem from the current versions of the concepts in `data`.
+/// `source` is the name of the NPU XML file, used in the description. `total`
+/// is the number of concepts of the complete code system, which differs from
+/// the number of concepts in `data` if these were filtered. Field values that
+/// cannot be represented are added to `losses`.
+pub fn build_code_system(
+    source: &str,
+    data: &Npubegreber,
+    content: &Content,
+    total: u32,
+    narrative: Narrative,
+    losses: &mut Vec<Loss>,
+) -> Result<CodeSystem, Error> {
+    let mut properties = vec![
+        Some(resources::CodeSystemProperty::builder()
+            .code("created_date".to_owned())
+            .uri("http://npu-terminology.org/property#created_date".to_owned())
+            .description("The date when the NPU code was created".to_owned())
+            .r#type(codes::PropertyType::DateTime)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("change_date".to_owned())
+            .uri("http://npu-terminology.org/property#change_date".to_owned())
+            .description("The date when the NPU code was last changed".to_owned())
+            .r#type(codes::PropertyType::DateTime)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("status".to_owned())
+            .uri("http://hl7.org/fhir/concept-properties#status".to_owned())
+            .description("A code that indicates the status of the concept. Typical values are active, experimental, deprecated, and retired".to_owned())
+            .r#type(codes::PropertyType::Code)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("inactive".to_owned())
+            .uri("http://hl7.org/fhir/concept-properties#inactive".to_owned())
+            .description("True if the concept is not considered active - e.g. not a valid concept any more. Property type is boolean, default value is false".to_owned())
+            .r#type(codes::PropertyType::Boolean)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("system".to_owned())
+            .uri("http://npu-terminology.org/property#system".to_owned())
+            .description("The system of the the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("sys_spec".to_owned())
+            .uri("http://npu-terminology.org/property#sys_spec".to_owned())
+            .description("The system specification of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("component".to_owned())
+            .uri("http://npu-terminology.org/property#component".to_owned())
+            .description("The component of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("comp_spec".to_owned())
+            .uri("http://npu-terminology.org/property#comp_spec".to_owned())
+            .description("The component specification of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("kind_of_property".to_owned())
+            .uri("http://npu-terminology.org/property#kind_of_property".to_owned())
+            .description("The kind of property of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("proc".to_owned())
+            .uri("http://npu-terminology.org/property#proc".to_owned())
+            .description("The procedure associated with the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("unit".to_owned())
+            .uri("http://npu-terminology.org/property#unit".to_owned())
+            .description("The unit of measurement for the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("specialty".to_owned())
+            .uri("http://npu-terminology.org/property#specialty".to_owned())
+            .description("The specialty associated with the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("context_dependent".to_owned())
+            .uri("http://npu-terminology.org/property#context_dependent".to_owned())
+            .description("Indicates if the NPU concept is context dependent".to_owned())
+            .r#type(codes::PropertyType::Boolean)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("scale_type".to_owned())
+            .uri("http://npu-terminology.org/property#scale_type".to_owned())
+            .description("The scale type of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("replaces".to_owned())
+            .uri("http://npu-terminology.org/property#replaces".to_owned())
+            .description("The retired NPU concept that this concept replaces".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("replaced_by".to_owned())
+            .uri("http://npu-terminology.org/property#replaced_by".to_owned())
+            .description("The NPU concept that replaces this retired concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("group".to_owned())
+            .uri("http://npu-terminology.org/property#group".to_owned())
+            .description("The group of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("change_comment".to_owned())
+            .uri("http://npu-terminology.org/property#change_comment".to_owned())
+            .description("The comment on the last change of the NPU concept".to_owned())
+            .r#type(codes::PropertyType::String)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("effective_from".to_owned())
+            .uri("http://npu-terminology.org/property#effective_from".to_owned())
+            .description("The date from which this version of the NPU concept is effective".to_owned())
+            .r#type(codes::PropertyType::DateTime)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        Some(resources::CodeSystemProperty::builder()
+            .code("effective_to".to_owned())
+            .uri("http://npu-terminology.org/property#effective_to".to_owned())
+            .description("The date until which this version of the NPU concept is effective".to_owned())
+            .r#type(codes::PropertyType::DateTime)
+            .build()
+            .context("CodeSystemProperty", None, None)?),
+        /* Some(resources::CodeSystemProperty::builder()
+            .code("current_version".to_owned())
+            .uri("http://npu-terminology.org/property#current_version".to_owned())
+            .description("Indicates if this is the current version of the NPU code".to_owned())
+            .r#type(codes::PropertyType::Boolean)
+            .build()
+            .context("CodeSystemProperty", None, None)?), */
+    ];
+    // Only merged inputs record the file of each concept.
+    if data
+        .npubegreb
+        .iter()
+        .any(|npubegreb| npubegreb.source.is_some())
+    {
+        properties.push(Some(
+            resources::CodeSystemProperty::builder()
+                .code("source".to_owned())
+                .uri("http://npu-terminology.org/property#source".to_owned())
+                .description(
+                    "The NPU XML file the concept was taken from when several were merged"
+                        .to_owned(),
+                )
+                .r#type(codes::PropertyType::String)
+                .build()
+                .context("CodeSystemProperty", None, None)?,
+        ));
+    }
+    let properties_json = serde_json::to_value(&properties)?;
+
+    let code_system = CodeSystem::builder()
+        .id("npu-terminology".to_owned())
+        .language("en-GB".to_owned())
+        .extension(vec![types::Extension::builder()
+            .url("http://hl7.org/fhir/StructureDefinition/codesystem-use-markdown".to_owned())
+            .value(
+                types::ExtensionValue::Boolean(false)
+            )
+            .build()
+            .context("Extension", None, None)?])
+        .url(URL.to_owned())
+        .identifier(vec![Some(
+            types::Identifier::builder()
+                .system("urn:ietf:rfc:3986".to_owned())
+                .value(format!("urn:oid:{OID}"))
+                .build()
+                .context("Identifier", None, None)?,
+        )])
+        .version(VERSION.to_owned())
+        .name("NPUTerminology".to_owned())
+        .title("NPU Terminology Code System".to_owned())
+        .status(codes::PublicationStatus::Active)
+        .experimental(false)
+        .publisher(PUBLISHER.to_owned())
+        .contact(contact()?)
+        .case_sensitive(true)
+        .compositional(false)
+        .version_needed(false)
+        .content(match content {
+            Content::Complete => codes::CodeSystemContentMode::Complete,
+            Content::NotPresent => codes::CodeSystemContentMode::NotPresent,
+            Content::Example(_) => codes::CodeSystemContentMode::Example,
+            Content::Fragment { .. } => codes::CodeSystemContentMode::Fragment,
+        })
+        .value_set("http://npu-terminology.org/fhir/ValueSet/NPUFull".to_owned())
+        .description(format!("NPU Terminology International edition Code System{}. Generated from {} on {}",
+            match content {
+                Content::Complete => String::new(),
+                Content::NotPresent => ", without concepts".to_owned(),
+                Content::Example(_) => ", with example concepts".to_owned(),
+                Content::Fragment { criteria } => format!(", fragment with the concepts selected by: {criteria}"),
+            },
+            source,
+            UtcDateTime::now()
+                .format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+                .context("CodeSystem", None, Some("description"))?
+        ))
+        .copyright("The ownership and intellectual property rights of NPU terminology are shared between the International Federation of Clinical Chemistry and Laboratory Medicine (IFCC) (www.ifcc.org) and the International Union of Pure and Applied Chemistry (IUPAC) (www.iupac.org).".to_owned())
+        .property(properties);
+
+    let mut concept = Vec::<Option<resources::CodeSystemConcept>>::new();
+
+    let limit = match content {
+        Content::NotPresent => 0,
+        Content::Example(limit) => *limit,
+        Content::Complete | Content::Fragment { .. } => usize::MAX,
+    };
+    for npubegreb in &data.npubegreb {
+        if !npubegreb.is_current() && npubegreb.current_version != "false" {
+            losses.push(Loss::new(
+                &npubegreb.npu_code,
+                "current_version",
+                &npubegreb.current_version,
+                "not \"true\", row skipped",
+            ));
+        }
+    }
+    for npubegreb in data.current().take(limit) {
+        let code = npubegreb.npu_code.as_str();
+        if npubegreb.active != "1" && npubegreb.active != "0" {
+            losses.push(Loss::new(
+                code,
+                "active",
+                &npubegreb.active,
+                "not \"1\", converted to retired",
+            ));
+        }
+        if !["", "Ja", "Nej"].contains(&npubegreb.context_dependent.as_str()) {
+            losses.push(Loss::new(
+                code,
+                "context_dependent",
+                &npubegreb.context_dependent,
+                "not \"Ja\", converted to false",
+            ));
+        }
+
+        concept.push(Some(
+            resources::CodeSystemConcept::builder()
+                .code(npubegreb.npu_code.clone())
+                .display(npubegreb.short_definition.clone())
+                .definition(remove_duplicate_spaces(
+                    &npubegreb.full_definition.replace("\n", ""),
+                ))
+                .property(
+                    vec![
+                        add_if_not_empty_date(
+                            "created_date".to_owned(),
+                            npubegreb.created_date.clone(),
+                            code,
+                            losses,
+                        ),
+                        add_if_not_empty_date(
+                            "change_date".to_owned(),
+                            npubegreb.change_date.clone(),
+                            code,
+                            losses,
+                        ),
+                        add_if_not_empty_code(
+                            "status".to_owned(),
+                            if npubegreb.active == "1" {
+                                "active".to_owned()
+                            } else {
+                                "retired".to_owned()
+                            },
+                            code,
+                        ),
+                        add_bool("inactive".to_owned(), npubegreb.active != "1", code),
+                        add_if_not_empty_string(
+                            "system".to_owned(),
+                            npubegreb.system.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "sys_spec".to_owned(),
+                            npubegreb.sys_spec.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "component".to_owned(),
+                            npubegreb.component.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "comp_spec".to_owned(),
+                            npubegreb.comp_spec.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "kind_of_property".to_owned(),
+                            npubegreb.kind_of_property.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string("proc".to_owned(), npubegreb.proc.clone(), code),
+                        add_if_not_empty_string("unit".to_owned(), npubegreb.unit.clone(), code),
+                        add_if_not_empty_string(
+                            "specialty".to_owned(),
+                            npubegreb.specialty.clone(),
+                            code,
+                        ),
+                        add_bool(
+                            "context_dependent".to_owned(),
+                            npubegreb.context_dependent == "Ja",
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "scale_type".to_owned(),
+                            npubegreb.scale_type.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "replaces".to_owned(),
+                            npubegreb.replaces.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string(
+                            "replaced_by".to_owned(),
+                            npubegreb.replaced_by.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_string("group".to_owned(), npubegreb.group.clone(), code),
+                        add_if_not_empty_string(
+                            "change_comment".to_owned(),
+                            npubegreb.change_comment.clone(),
+                            code,
+                        ),
+                        add_if_not_empty_date(
+                            "effective_from".to_owned(),
+                            npubegreb.effective_from.clone(),
+                            code,
+                            losses,
+                        ),
+                        add_if_not_empty_date(
+                            "effective_to".to_owned(),
+                            npubegreb.effective_to.clone(),
+                            code,
+                            losses,
+                        ),
+                        /* add_bool(
+                            "current_version".to_owned(),
+                            npubegreb.current_version == "true",
+                            code,
+                        ), */
+                        add_if_not_empty_string(
+                            "source".to_owned(),
+                            npubegreb.source.clone().unwrap_or_default(),
+                            code,
+                        ),
+                    ]
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .filter(Option::is_some)
+                    .collect(),
+                )
+                .build()
+                .context("CodeSystemConcept", Some(code), None)?,
+        ));
+    }
+
+    let code_system = code_system
+        .text(
+            types::Narrative::builder()
+                .status(if narrative::is_generated(narrative, content) {
+                    codes::NarrativeStatus::Generated
+                } else {
+                    codes::NarrativeStatus::Empty
+                })
+                .div(narrative::div(
+                    narrative,
+                    content,
+                    &properties_json,
+                    &serde_json::to_value(&concept)?,
+                    data,
+                    total,
+                ))
+                .build()
+                .context("Narrative", None, None)?,
+        )
+        .count(total)
+        .concept(concept);
+    let code_system = code_system.build().context("CodeSystem", None, None)?;
+
+    Ok(code_system)
+}
+
+/// The contact details of the NPU publisher.
+pub fn contact() -> Result<Vec<Option<types::ContactDetail>>, Error> {
+    Ok(vec![Some(
+        types::ContactDetail::builder()
+            .name("Danish National eHealth Authority".to_owned())
+            .telecom(vec![
+                Some(
+                    types::ContactPoint::builder()
+                        .system(codes::ContactPointSystem::Url)
+                        .value("https://npu-terminology.org".to_owned())
+                        .build()
+                        .context("ContactPoint", None, None)?,
+                ),
+                Some(
+                    types::ContactPoint::builder()
+                        .system(codes::ContactPointSystem::Email)
+                        .value("npu-terminology@sundhedsdata.dk".to_owned())
+                        .build()
+                        .context("ContactPoint", None, None)?,
+                ),
+            ])
+            .build()
+            .context("ContactDetail", None, None)?,
+    )])
+}
+
+pub fn remove_duplicate_spaces(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut prev_space = false;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !prev_space {
+                result.push(' ');
+                prev_space = true;
+            }
+        } else {
+            result.push(c);
+            prev_space = false;
+        }
+    }
+    result.trim().to_string()
+}
+
+fn add_if_not_empty_string(
+    property: String,
+    str: String,
+    npu_code: &str,
+) -> Result<Option<resources::CodeSystemConceptProperty>, Error> {
+    if str.is_empty() {
+        return Ok(None);
+    }
+    add_property(
+        property,
+        resources::CodeSystemConceptPropertyValue::String(str),
+        npu_code,
+    )
+}
+
+fn add_if_not_empty_code(
+    property: String,
+    str: String,
+    npu_code: &str,
+) -> Result<Option<resources::CodeSystemConceptProperty>, Error> {
+    if str.is_empty() {
+        return Ok(None);
+    }
+    add_property(
+        property,
+        resources::CodeSystemConceptPropertyValue::Code(str),
+        npu_code,
+    )
+}
+
+fn add_if_not_empty_date(
+    property: String,
+    str: String,
+    npu_code: &str,
+    losses: &mut Vec<Loss>,
+) -> Result<Option<resources::CodeSystemConceptProperty>, Error> {
+    if str.is_empty() {
+        return Ok(None);
+    }
+    let Ok(dt) = parse_date(&str) else {
+        losses.push(Loss::new(
+            npu_code,
+            &property,
+            &str,
+            "unparseable date, dropped",
+        ));
+        return Ok(None);
+    };
+    add_property(
+        property,
+        resources::CodeSystemConceptPropertyValue::DateTime(fhir_sdk::DateTime::DateTime(
+            dt.to_offset(UtcOffset::UTC).into(),
+        )),
+        npu_code,
+    )
+}
+
+fn add_bool(
+    property: String,
+    b: bool,
+    npu_code: &str,
+) -> Result<Option<resources::CodeSystemConceptProperty>, Error> {
+    add_property(
+        property,
+        resources::CodeSystemConceptPropertyValue::Boolean(b),
+        npu_code,
+    )
+}
+
+fn add_property(
+    property: String,
+    value: resources::CodeSystemConceptPropertyValue,
+    npu_code: &str,
+) -> Result<Option<resources::CodeSystemConceptProperty>, Error> {
+    resources::CodeSystemConceptProperty::builder()
+        .code(property.clone())
+        .value(value)
+        .build()
+        .context("CodeSystemConceptProperty", Some(npu_code), Some(&property))
+        .map(Some)
+}
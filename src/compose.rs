@@ -0,0 +1,203 @@
+//! Lookup of the pre-coordinated NPU codes for a combination of axis values,
+//! e.g. to map a local analysis described by specimen and analyte to NPU.
+//!
+//! Each axis value matches the full or the abbreviated field, ignoring case
+//! and the spacing around semicolons and parentheses. Axes that are not given
+//! match any value; an empty value only matches an empty field.
+
+use clap::Args;
+use serde::Serialize;
+
+use crate::code_system::remove_duplicate_spaces;
+use crate::definition::{self, ParseError, axis_text, normalize};
+use crate::npu_concepts::{Npubegreb, Npubegreber};
+
+/// Codes that differ in a single axis are reported when no code matches, up
+/// to this many.
+const MAX_NEAR_MATCHES: usize = 20;
+
+/// The axis values to look up. `None` matches any value.
+#[derive(Args)]
+pub struct Axes {
+    #[arg(long, help_heading = "Axes")]
+    /// System, e.g. Plasma or P
+    pub system: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Specification of the system
+    pub sys_spec: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Component, e.g. Sodium ion
+    pub component: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Specification of the component
+    pub comp_spec: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Kind of property, e.g. substance concentration or subst.c.
+    pub kind_of_property: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Procedure
+    pub proc: Option<String>,
+
+    #[arg(long, help_heading = "Axes")]
+    /// Unit, e.g. millimole per litre or mmol/L
+    pub unit: Option<String>,
+}
+
+impl Axes {
+    /// The axes of an NPU definition such as `P—Sodium ion; subst.c. = ? mmol/L`,
+    /// short or full. Axes without a specification in the definition only
+    /// match codes without one.
+    pub fn from_definition(text: &str) -> Result<Self, ParseError> {
+        let text = remove_duplicate_spaces(text);
+        let definition = definition::parse(&text)?;
+        let specification =
+            |axis: &definition::Axis| Some(axis.specification.unwrap_or_default().to_owned());
+        Ok(Axes {
+            system: Some(definition.system.name.to_owned()),
+            sys_spec: specification(&definition.system),
+            component: Some(definition.component.name.to_owned()),
+            comp_spec: specification(&definition.component),
+            kind_of_property: Some(definition.kind_of_property.name.to_owned()),
+            proc: specification(&definition.kind_of_property),
+            unit: Some(definition.unit.to_owned()),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.system.is_none()
+            && self.sys_spec.is_none()
+            && self.component.is_none()
+            && self.comp_spec.is_none()
+            && self.kind_of_property.is_none()
+            && self.proc.is_none()
+            && self.unit.is_none()
+    }
+
+    /// The axes of `npubegreb` that do not match.
+    fn mismatches(&self, npubegreb: &Npubegreb) -> Vec<&'static str> {
+        let mut mismatches = Vec::new();
+        for (axis, name, specification, fields) in [
+            (
+                "system",
+                &self.system,
+                &self.sys_spec,
+                [
+                    (&npubegreb.system, &npubegreb.sys_spec),
+                    (&npubegreb.system_short, &npubegreb.sys_spec_short),
+                ],
+            ),
+            (
+                "component",
+                &self.component,
+                &self.comp_spec,
+                [
+                    (&npubegreb.component, &npubegreb.comp_spec),
+                    (&npubegreb.component_short, &npubegreb.comp_spec_short),
+                ],
+            ),
+            (
+                "kind_of_property",
+                &self.kind_of_property,
+                &self.proc,
+                [
+                    (&npubegreb.kind_of_property, &npubegreb.proc),
+                    (&npubegreb.kind_of_property_short, &npubegreb.proc_short),
+                ],
+            ),
+        ] {
+            let matches = fields.iter().any(|(field, field_specification)| {
+                axis_matches(name, specification, field, field_specification)
+            });
+            if !matches {
+                mismatches.push(axis);
+            }
+        }
+        if let Some(unit) = &self.unit
+            && !equal(unit, &npubegreb.unit)
+            && !equal(unit, &npubegreb.unit_short)
+        {
+            mismatches.push("unit");
+        }
+        mismatches
+    }
+}
+
+/// Whether an axis with the given `name` and `specification` matches the
+/// fields of a code. A name without a specification also matches the whole
+/// axis text, e.g. `Fibrinopeptide B[beta](1-42)`.
+fn axis_matches(
+    name: &Option<String>,
+    specification: &Option<String>,
+    field: &str,
+    field_specification: &str,
+) -> bool {
+    match (name, specification) {
+        (None, None) => true,
+        (None, Some(specification)) => equal(specification, field_specification),
+        (Some(name), Some(specification)) => equal(
+            &axis_text(name, specification),
+            &axis_text(field, field_specification),
+        ),
+        (Some(name), None) => {
+            equal(name, field) || equal(name, &axis_text(field, field_specification))
+        }
+    }
+}
+
+fn equal(a: &str, b: &str) -> bool {
+    normalize(a).to_lowercase() == normalize(b).to_lowercase()
+}
+
+#[derive(Serialize)]
+pub struct Match<'a> {
+    pub code: &'a str,
+    pub display: String,
+    pub active: bool,
+    /// The axes that differ, empty for an exact match.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub differs_in: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct Composition<'a> {
+    /// The codes matching all axes.
+    pub matches: Vec<Match<'a>>,
+    /// If no code matches, the codes that differ in one axis.
+    pub near_matches: Vec<Match<'a>>,
+}
+
+/// The current codes in `data` that match `axes`, retired codes only if
+/// `include_retired`.
+pub fn compose<'a>(data: &'a Npubegreber, axes: &Axes, include_retired: bool) -> Composition<'a> {
+    let mut matches = Vec::new();
+    let mut near_matches = Vec::new();
+    for npubegreb in data.current() {
+        if !include_retired && npubegreb.active != "1" {
+            continue;
+        }
+        let differs_in = axes.mismatches(npubegreb);
+        let candidate = Match {
+            code: &npubegreb.npu_code,
+            display: remove_duplicate_spaces(&npubegreb.short_definition),
+            active: npubegreb.active == "1",
+            differs_in,
+        };
+        match candidate.differs_in.len() {
+            0 => matches.push(candidate),
+            1 if near_matches.len() < MAX_NEAR_MATCHES => near_matches.push(candidate),
+            _ => {}
+        }
+    }
+    if !matches.is_empty() {
+        near_matches.clear();
+    }
+    Composition {
+        matches,
+        near_matches,
+    }
+}
@@ -0,0 +1,277 @@
+//! Parser for NPU definitions such as
+//! `Plasma(Fasting)—Glucose; substance concentration(proc.) = ? millimole per litre`,
+//! i.e. `System(spec)—Component(spec); Kind-of-property(procedure) = ? unit`.
+
+use std::fmt;
+
+/// A system, component or kind-of-property part of a definition, with its
+/// specification or procedure in parentheses.
+pub struct Axis<'a> {
+    /// The part as written, e.g. `Lymcs(Marrow)`.
+    pub text: &'a str,
+    /// The part without a trailing parenthesized specification.
+    pub name: &'a str,
+    pub specification: Option<&'a str>,
+}
+
+pub struct Definition<'a> {
+    pub system: Axis<'a>,
+    pub component: Axis<'a>,
+    /// The kind of property, with the procedure as its specification.
+    pub kind_of_property: Axis<'a>,
+    /// The unit after `= ?`, empty for unitless properties.
+    pub unit: &'a str,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The definition has no `= ?` before the unit.
+    MissingResult,
+    /// The definition has no em dash after the system.
+    MissingSystem,
+    /// The definition has no semicolon between component and kind of property.
+    MissingKindOfProperty,
+    /// Parentheses or brackets are not balanced.
+    Unbalanced,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingResult => write!(f, "no \"= ?\" before the unit"),
+            ParseError::MissingSystem => write!(f, "no em dash after the system"),
+            ParseError::MissingKindOfProperty => {
+                write!(f, "no semicolon before the kind of property")
+            }
+            ParseError::Unbalanced => write!(f, "unbalanced parentheses or brackets"),
+        }
+    }
+}
+
+/// Parse a definition whose whitespace has been normalized with
+/// `remove_duplicate_spaces`.
+pub fn parse(definition: &str) -> Result<Definition<'_>, ParseError> {
+    let (left, unit) = definition
+        .rsplit_once("= ?")
+        .ok_or(ParseError::MissingResult)?;
+    let (system, rest) = left.split_once('—').ok_or(ParseError::MissingSystem)?;
+    // The procedure may contain semicolons, so split at the last one outside
+    // parentheses.
+    let separator = top_level(rest)?
+        .filter(|(_, c)| *c == ';')
+        .map(|(i, _)| i)
+        .last()
+        .ok_or(ParseError::MissingKindOfProperty)?;
+    let (component, kind_of_property) = (&rest[..separator], &rest[separator + 1..]);
+
+    Ok(Definition {
+        system: axis(system)?,
+        component: axis(component)?,
+        kind_of_property: axis(kind_of_property)?,
+        unit: unit.trim(),
+    })
+}
+
+fn axis(text: &str) -> Result<Axis<'_>, ParseError> {
+    let text = text.trim();
+    let specification = text.strip_suffix(')').and_then(|inner| {
+        // The opening parenthesis matching the final one.
+        let mut depth = 0;
+        for (i, c) in inner.char_indices().rev() {
+            match c {
+                ')' => depth += 1,
+                '(' if depth == 0 => return Some(i),
+                '(' => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    });
+    Ok(match specification {
+        Some(open) => Axis {
+            text,
+            name: text[..open].trim(),
+            specification: Some(text[open + 1..text.len() - 1].trim()),
+        },
+        None if text.ends_with(')') => return Err(ParseError::Unbalanced),
+        None => Axis {
+            text,
+            name: text,
+            specification: None,
+        },
+    })
+}
+
+/// The characters of `s` outside parentheses and brackets, with their byte
+/// offsets.
+fn top_level(s: &str) -> Result<impl Iterator<Item = (usize, char)> + '_, ParseError> {
+    let mut depth = 0i32;
+    let mut top_level = Vec::new();
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ if depth == 0 => top_level.push((i, c)),
+            _ => {}
+        }
+        if depth < 0 {
+            return Err(ParseError::Unbalanced);
+        }
+    }
+    if depth != 0 {
+        return Err(ParseError::Unbalanced);
+    }
+    Ok(top_level.into_iter())
+}
+
+/// The text of an axis from its separate name and specification fields, e.g.
+/// `Lymcs(Marrow)` for `system_short` and `sys_spec_short`.
+pub fn axis_text(name: &str, specification: &str) -> String {
+    let (name, specification) = (name.trim(), specification.trim());
+    if specification.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name}({specification})")
+    }
+}
+
+/// `s` with the spacing around semicolons and parentheses removed, which
+/// differs between the definitions and the separate fields.
+pub fn normalize(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    for word in s.split_whitespace() {
+        let after_separator = normalized.ends_with([';', '(', ')']);
+        if !normalized.is_empty() && !after_separator && !word.starts_with([';', '(', ')']) {
+            normalized.push(' ');
+        }
+        normalized.push_str(word);
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_system::remove_duplicate_spaces;
+    use crate::npu_concepts::test_data;
+
+    #[test]
+    fn test_data_definitions_match_fields() {
+        for npubegreb in test_data().npubegreb {
+            let full_definition = remove_duplicate_spaces(&npubegreb.full_definition);
+            let definition =
+                parse(&full_definition).unwrap_or_else(|e| panic!("{}: {e}", npubegreb.npu_code));
+            for (axis, name, specification) in [
+                (&definition.system, &npubegreb.system, &npubegreb.sys_spec),
+                (
+                    &definition.component,
+                    &npubegreb.component,
+                    &npubegreb.comp_spec,
+                ),
+                (
+                    &definition.kind_of_property,
+                    &npubegreb.kind_of_property,
+                    &npubegreb.proc,
+                ),
+            ] {
+                assert_eq!(
+                    normalize(axis.text),
+                    normalize(&axis_text(name, specification)),
+                    "{}",
+                    npubegreb.npu_code
+                );
+            }
+            assert_eq!(definition.unit, npubegreb.unit.trim());
+        }
+    }
+
+    #[test]
+    fn specification_in_parentheses() {
+        let definition = parse(
+            "Lymphocytes(Bone marrow)—T-lymphocytes(CD4+); number fraction = ? 10<sup>-2</sup>",
+        )
+        .unwrap();
+        assert_eq!(definition.system.name, "Lymphocytes");
+        assert_eq!(definition.system.specification, Some("Bone marrow"));
+        assert_eq!(definition.component.name, "T-lymphocytes");
+        assert_eq!(definition.component.specification, Some("CD4+"));
+        assert_eq!(definition.kind_of_property.name, "number fraction");
+        assert_eq!(definition.kind_of_property.specification, None);
+        assert_eq!(definition.unit, "10<sup>-2</sup>");
+    }
+
+    #[test]
+    fn nested_parentheses() {
+        let definition = parse(
+            "Plasma—Fibrinopeptide B[beta](1-42); substance concentration(immunological(ELISA);procedure) = ? nanomole per litre",
+        )
+        .unwrap();
+        assert_eq!(definition.component.name, "Fibrinopeptide B[beta]");
+        assert_eq!(definition.component.specification, Some("1-42"));
+        assert_eq!(
+            definition.kind_of_property.specification,
+            Some("immunological(ELISA);procedure")
+        );
+    }
+
+    #[test]
+    fn procedure_with_semicolons() {
+        let definition =
+            parse("Urine—Acebutolol; arbitrary concentration(IOC Screen; 0 1) = ?").unwrap();
+        assert_eq!(definition.component.text, "Acebutolol");
+        assert_eq!(definition.kind_of_property.name, "arbitrary concentration");
+        assert_eq!(
+            definition.kind_of_property.specification,
+            Some("IOC Screen; 0 1")
+        );
+        assert_eq!(definition.unit, "");
+    }
+
+    #[test]
+    fn component_with_comma() {
+        let definition = parse(
+            "Plasma—Alkaline phosphatase, liver type; catalytic-activity concentration(37 °C;procedure) = ? microkatal per litre",
+        )
+        .unwrap();
+        assert_eq!(
+            definition.component.text,
+            "Alkaline phosphatase, liver type"
+        );
+        assert_eq!(definition.unit, "microkatal per litre");
+    }
+
+    #[test]
+    fn malformed_definitions() {
+        let error = |definition| parse(definition).err().map(|e| e.to_string());
+        assert_eq!(
+            error("Plasma—Sodium ion; substance concentration"),
+            Some(ParseError::MissingResult.to_string())
+        );
+        assert_eq!(
+            error("Plasma Sodium ion; substance concentration = ? millimole per litre"),
+            Some(ParseError::MissingSystem.to_string())
+        );
+        assert_eq!(
+            error("Plasma—Sodium ion substance concentration = ? millimole per litre"),
+            Some(ParseError::MissingKindOfProperty.to_string())
+        );
+        assert_eq!(
+            error("Plasma—Sodium ion(; substance concentration = ? millimole per litre"),
+            Some(ParseError::Unbalanced.to_string())
+        );
+        assert_eq!(
+            error("Plasma)—Sodium ion; substance concentration = ? millimole per litre"),
+            Some(ParseError::Unbalanced.to_string())
+        );
+    }
+
+    #[test]
+    fn normalize_spacing() {
+        assert_eq!(
+            normalize("substance concentration ( pauciscale ; procedure )"),
+            "substance concentration(pauciscale;procedure)"
+        );
+        assert_eq!(axis_text(" Lymcs ", " Marrow "), "Lymcs(Marrow)");
+        assert_eq!(axis_text("Urine", ""), "Urine");
+    }
+}
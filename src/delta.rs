@@ -0,0 +1,189 @@
+//! Selection of the NPU concepts that were added or changed since an earlier
+//! release, for a delta CodeSystem that updates a server which has that
+//! release loaded.
+//!
+//! The changes are found either from the dates of the concepts or by comparing
+//! the current versions with those of the earlier release file. Like the
+//! filters, a code is selected with all its rows.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use clap::Args;
+use time::Date;
+
+use crate::error::Error;
+use crate::filter::parse_day;
+use crate::npu_concepts::{Npubegreb, Npubegreber, parse_date};
+
+#[derive(Args)]
+pub struct Delta {
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_day, help_heading = "Delta")]
+    /// Only include codes created, changed or effective on or after this day
+    pub since: Option<Date>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "since",
+        help_heading = "Delta"
+    )]
+    /// Only include codes that are new or changed compared with this earlier
+    /// NPU XML file
+    pub previous: Option<String>,
+}
+
+impl Delta {
+    /// Keep the rows of the codes that were added or changed since `since`,
+    /// or compared with `previous`, the data of the `--previous` file.
+    pub fn apply(
+        &self,
+        data: Npubegreber,
+        previous: Option<&Npubegreber>,
+    ) -> Result<Npubegreber, Error> {
+        let mut selected = HashSet::<String>::new();
+        if let Some(since) = self.since {
+            for npubegreb in data.current() {
+                let changed = [
+                    &npubegreb.created_date,
+                    &npubegreb.change_date,
+                    &npubegreb.effective_from,
+                ]
+                .into_iter()
+                .filter_map(|date| parse_date(date).ok())
+                .any(|date| date.date() >= since);
+                if changed {
+                    selected.insert(npubegreb.npu_code.clone());
+                }
+            }
+        } else if let Some(previous) = previous {
+            let previous: HashMap<&str, &Npubegreb> = previous
+                .current()
+                .map(|npubegreb| (npubegreb.npu_code.as_str(), npubegreb))
+                .collect();
+            for npubegreb in data.current() {
+                let changed = match previous.get(npubegreb.npu_code.as_str()) {
+                    Some(earlier) => {
+                        serde_json::to_value(earlier)? != serde_json::to_value(npubegreb)?
+                    }
+                    None => true,
+                };
+                if changed {
+                    selected.insert(npubegreb.npu_code.clone());
+                }
+            }
+        } else {
+            return Ok(data);
+        }
+
+        let Npubegreber { text, npubegreb } = data;
+        Ok(Npubegreber {
+            text,
+            npubegreb: npubegreb
+                .into_iter()
+                .filter(|npubegreb| selected.contains(&npubegreb.npu_code))
+                .collect(),
+        })
+    }
+}
+
+/// The codes with a current version in `previous` but not in `data`, which a
+/// delta CodeSystem cannot remove.
+pub fn removed_codes<'a>(previous: &'a Npubegreber, data: &Npubegreber) -> Vec<&'a str> {
+    let codes: HashSet<&str> = data
+        .current()
+        .map(|npubegreb| npubegreb.npu_code.as_str())
+        .collect();
+    previous
+        .current()
+        .map(|npubegreb| npubegreb.npu_code.as_str())
+        .filter(|code| !codes.contains(code))
+        .collect()
+}
+
+/// The delta criterion, for the description of the CodeSystem.
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(since) = self.since {
+            write!(f, "added or changed on or after {since}")?;
+        } else if let Some(previous) = &self.previous {
+            write!(f, "added or changed since {previous}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        delta: Delta,
+    }
+
+    fn delta(args: &[&str]) -> Result<Delta, clap::Error> {
+        Cli::try_parse_from(std::iter::once("npu-to-fhir").chain(args.iter().copied()))
+            .map(|cli| cli.delta)
+    }
+
+    /// The codes of the rows kept by `delta`, one per row.
+    fn rows(delta: &Delta, previous: Option<&Npubegreber>) -> Vec<String> {
+        delta
+            .apply(test_data(), previous)
+            .unwrap()
+            .npubegreb
+            .into_iter()
+            .map(|npubegreb| npubegreb.npu_code)
+            .collect()
+    }
+
+    #[test]
+    fn since_selects_codes_with_a_later_date() {
+        let delta = delta(&["--since", "2021-10-25"]).unwrap();
+        assert_eq!(rows(&delta, None), ["NPU01012", "NPU01012", "NPU63222"]);
+        assert_eq!(delta.to_string(), "added or changed on or after 2021-10-25");
+    }
+
+    #[test]
+    fn previous_selects_added_and_changed_codes() {
+        let mut previous = test_data();
+        previous
+            .npubegreb
+            .retain(|npubegreb| npubegreb.npu_code != "NPU63222");
+        for npubegreb in &mut previous.npubegreb {
+            if npubegreb.npu_code == "NPU01013" && npubegreb.is_current() {
+                npubegreb.unit = "millimole per litre".to_owned();
+            }
+            // Earlier versions are not compared.
+            if npubegreb.npu_code == "NPU01001" && !npubegreb.is_current() {
+                npubegreb.unit = "changed".to_owned();
+            }
+        }
+        let delta = delta(&["--previous", "npu_previous.xml"]).unwrap();
+        assert_eq!(
+            rows(&delta, Some(&previous)),
+            ["NPU01013", "NPU01013", "NPU63222"]
+        );
+        assert_eq!(delta.to_string(), "added or changed since npu_previous.xml");
+    }
+
+    #[test]
+    fn removed_codes_are_reported() {
+        let mut data = test_data();
+        data.npubegreb
+            .retain(|npubegreb| npubegreb.npu_code != "NPU01005");
+        assert_eq!(removed_codes(&test_data(), &data), ["NPU01005"]);
+        assert!(removed_codes(&data, &test_data()).is_empty());
+    }
+
+    #[test]
+    fn since_and_previous_conflict() {
+        assert!(delta(&["--since", "2021-10-25", "--previous", "npu_previous.xml"]).is_err());
+        let delta = delta(&[]).unwrap();
+        assert_eq!(rows(&delta, None).len(), test_data().npubegreb.len());
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+/// Errors of the converters. Each kind has its own process exit code so that
+/// pipelines can branch on the cause; 1 is used when a run completes but
+/// finds problems (`check`, `--validate`) and 2 by clap for usage errors.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading an input or writing an output file failed.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// The NPU XML file could not be parsed.
+    XmlParse {
+        path: String,
+        source: serde_xml_rs::Error,
+    },
+    /// A JSON input, e.g. of FHIR resources to migrate, could not be parsed.
+    /// `line` is the line of an NDJSON file.
+    JsonParse {
+        path: String,
+        line: Option<usize>,
+        source: serde_json::Error,
+    },
+    /// More NPU XML records were malformed than allowed by `--max-errors`.
+    MalformedRecords {
+        path: String,
+        count: usize,
+        max_errors: usize,
+    },
+    /// A field value of an NPU record cannot be converted.
+    RecordValidation {
+        npu_code: String,
+        field: String,
+        message: String,
+    },
+    /// A `fhir_sdk` builder rejected its input.
+    FhirBuild {
+        element: &'static str,
+        npu_code: Option<String>,
+        field: Option<String>,
+        message: String,
+    },
+    /// Serializing an output format failed.
+    Serialization {
+        format: &'static str,
+        message: String,
+    },
+}
+
+impl Error {
+    pub fn io(path: impl Into<String>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io { .. } => 3,
+            Error::XmlParse { .. } | Error::JsonParse { .. } | Error::MalformedRecords { .. } => 4,
+            Error::RecordValidation { .. } => 5,
+            Error::FhirBuild { .. } => 6,
+            Error::Serialization { .. } => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{path}: {source}"),
+            Error::XmlParse { path, source } => {
+                write!(f, "{path}: failed to parse NPU XML: {source}")
+            }
+            Error::JsonParse { path, line, source } => {
+                write!(f, "{path}")?;
+                if let Some(line) = line {
+                    write!(f, ":{line}")?;
+                }
+                write!(f, ": failed to parse JSON: {source}")
+            }
+            Error::MalformedRecords {
+                path,
+                count,
+                max_errors,
+            } => write!(
+                f,
+                "{path}: more than {max_errors} malformed records, stopped after {count}"
+            ),
+            Error::RecordValidation {
+                npu_code,
+                field,
+                message,
+            } => write!(f, "{npu_code} {field}: {message}"),
+            Error::FhirBuild {
+                element,
+                npu_code,
+                field,
+                message,
+            } => {
+                write!(f, "failed to build {element}")?;
+                if let Some(npu_code) = npu_code {
+                    write!(f, " for {npu_code}")?;
+                }
+                if let Some(field) = field {
+                    write!(f, " {field}")?;
+                }
+                write!(f, ": {message}")
+            }
+            Error::Serialization { format, message } => {
+                write!(f, "failed to serialize {format}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::XmlParse { source, .. } => Some(source),
+            Error::JsonParse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization {
+            format: "JSON",
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Writing a text format to a `String` only fails if a `Display` implementation
+/// does.
+impl From<fmt::Error> for Error {
+    fn from(e: fmt::Error) -> Self {
+        Error::Serialization {
+            format: "text",
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Serialization {
+            format: "SQLite",
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Adds the context of a `fhir_sdk` builder to its `build()` result.
+pub trait BuildContext<T> {
+    /// `element` is the FHIR type being built, `npu_code` and `field` the NPU
+    /// record and field it is built from, if any.
+    fn context(
+        self,
+        element: &'static str,
+        npu_code: Option<&str>,
+        field: Option<&str>,
+    ) -> Result<T, Error>;
+}
+
+impl<T, E: fmt::Display> BuildContext<T> for Result<T, E> {
+    fn context(
+        self,
+        element: &'static str,
+        npu_code: Option<&str>,
+        field: Option<&str>,
+    ) -> Result<T, Error> {
+        self.map_err(|e| Error::FhirBuild {
+            element,
+            npu_code: npu_code.map(str::to_owned),
+            field: field.map(str::to_owned),
+            message: e.to_string(),
+        })
+    }
+}
@@ -0,0 +1,271 @@
+//! FHIR XML serialization.
+//!
+//! `fhir_sdk` only implements the JSON representation, so resources are first
+//! serialized to a `serde_json::Value` and then mapped to XML following the
+//! rules of <https://hl7.org/fhir/R5/xml.html>: primitives become elements with
+//! a `value` attribute, `_name` entries carry the primitive's id and
+//! extensions, element ids and extension urls become attributes, and the
+//! narrative `div` is copied through as XHTML.
+//!
+//! Key order is preserved (serde_json `preserve_order`), so the element order
+//! is the one `fhir_sdk` serializes, which follows the FHIR definitions.
+
+use serde::Serialize;
+use serde::ser::Error as _;
+use serde_json::{Map, Value};
+
+use crate::xml::escape;
+
+const FHIR_NAMESPACE: &str = "http://hl7.org/fhir";
+
+/// Serialize a FHIR resource to an indented FHIR XML document.
+pub fn to_string_pretty<T: Serialize>(resource: &T) -> Result<String, serde_json::Error> {
+    let value = serde_json::to_value(resource)?;
+    let Value::Object(map) = value else {
+        return Err(serde_json::Error::custom(
+            "FHIR resource did not serialize to a JSON object",
+        ));
+    };
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    write_resource(&mut xml, &map, 0, true)?;
+    Ok(xml)
+}
+
+fn write_resource(
+    xml: &mut String,
+    map: &Map<String, Value>,
+    depth: usize,
+    root: bool,
+) -> Result<(), serde_json::Error> {
+    let resource_type = map
+        .get("resourceType")
+        .and_then(Value::as_str)
+        .ok_or_else(|| serde_json::Error::custom("FHIR resource without resourceType"))?;
+
+    indent(xml, depth);
+    xml.push('<');
+    xml.push_str(resource_type);
+    if root {
+        push_attribute(xml, "xmlns", FHIR_NAMESPACE);
+    }
+    xml.push_str(">\n");
+    // The resource id is an element, unlike the id of a datatype or backbone
+    // element which is an attribute.
+    write_children(xml, map, depth + 1, &[])?;
+    close_tag(xml, resource_type, depth);
+    Ok(())
+}
+
+/// Write all entries of `map` as child elements, except `resourceType`, the
+/// keys in `attributes` and the `_name` companions of primitives.
+fn write_children(
+    xml: &mut String,
+    map: &Map<String, Value>,
+    depth: usize,
+    attributes: &[&str],
+) -> Result<(), serde_json::Error> {
+    for (key, value) in map {
+        if key == "resourceType" || attributes.contains(&key.as_str()) {
+            continue;
+        }
+        if let Some(name) = key.strip_prefix('_') {
+            // A primitive that only has an id or extensions and no value.
+            if !map.contains_key(name) {
+                write_element(xml, name, &Value::Null, Some(value), depth)?;
+            }
+            continue;
+        }
+        let primitive_extension = map.get(&format!("_{key}"));
+        write_element(xml, key, value, primitive_extension, depth)?;
+    }
+    Ok(())
+}
+
+fn write_element(
+    xml: &mut String,
+    name: &str,
+    value: &Value,
+    primitive_extension: Option<&Value>,
+    depth: usize,
+) -> Result<(), serde_json::Error> {
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let item_extension = primitive_extension
+                    .and_then(Value::as_array)
+                    .and_then(|extensions| extensions.get(i))
+                    .filter(|extension| !extension.is_null());
+                write_element(xml, name, item, item_extension, depth)?;
+            }
+        }
+        Value::Object(map) if map.contains_key("resourceType") => {
+            indent(xml, depth);
+            xml.push('<');
+            xml.push_str(name);
+            xml.push_str(">\n");
+            write_resource(xml, map, depth + 1, false)?;
+            close_tag(xml, name, depth);
+        }
+        Value::Object(map) => {
+            let attributes: &[&str] = if name == "extension" || name == "modifierExtension" {
+                &["id", "url"]
+            } else {
+                &["id"]
+            };
+            open_tag(xml, name, map, attributes, depth);
+            let has_children = map.keys().any(|key| !attributes.contains(&key.as_str()));
+            if has_children {
+                xml.push_str(">\n");
+                write_children(xml, map, depth + 1, attributes)?;
+                close_tag(xml, name, depth);
+            } else {
+                xml.push_str("/>\n");
+            }
+        }
+        Value::String(div) if name == "div" => {
+            indent(xml, depth);
+            xml.push_str(div.trim());
+            xml.push('\n');
+        }
+        primitive => {
+            let empty = Map::new();
+            let extension = match primitive_extension {
+                Some(Value::Object(map)) => map,
+                _ => &empty,
+            };
+            open_tag(xml, name, extension, &["id"], depth);
+            match primitive {
+                Value::Null => {}
+                Value::String(s) => push_attribute(xml, "value", s),
+                other => push_attribute(xml, "value", &other.to_string()),
+            }
+            if extension.keys().any(|key| key != "id") {
+                xml.push_str(">\n");
+                write_children(xml, extension, depth + 1, &["id"])?;
+                close_tag(xml, name, depth);
+            } else {
+                xml.push_str("/>\n");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open_tag(
+    xml: &mut String,
+    name: &str,
+    map: &Map<String, Value>,
+    attributes: &[&str],
+    depth: usize,
+) {
+    indent(xml, depth);
+    xml.push('<');
+    xml.push_str(name);
+    for attribute in attributes {
+        if let Some(Value::String(value)) = map.get(*attribute) {
+            push_attribute(xml, attribute, value);
+        }
+    }
+}
+
+fn close_tag(xml: &mut String, name: &str, depth: usize) {
+    indent(xml, depth);
+    xml.push_str("</");
+    xml.push_str(name);
+    xml.push_str(">\n");
+}
+
+fn push_attribute(xml: &mut String, name: &str, value: &str) {
+    xml.push(' ');
+    xml.push_str(name);
+    xml.push_str("=\"");
+    xml.push_str(&escape(value));
+    xml.push('"');
+}
+
+fn indent(xml: &mut String, depth: usize) {
+    for _ in 0..depth {
+        xml.push_str("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_system::{Content, build_code_system, concept_count};
+    use crate::narrative::Narrative;
+    use crate::npu_concepts::test_data;
+    use serde_json::json;
+
+    /// The position of `needle` in `xml`, which must contain it.
+    fn position(xml: &str, needle: &str) -> usize {
+        xml.find(needle)
+            .unwrap_or_else(|| panic!("{needle:?} not in the XML"))
+    }
+
+    #[test]
+    fn code_system_elements_follow_the_fhir_order() {
+        let data = test_data();
+        let code_system = build_code_system(
+            "npu_test_data.xml",
+            &data,
+            &Content::Complete,
+            concept_count(&data),
+            Narrative::Summary,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let xml = to_string_pretty(&code_system).unwrap();
+
+        assert!(xml.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<CodeSystem xmlns=\"http://hl7.org/fhir\">\n"
+        ));
+        let order = [
+            "  <text>",
+            "  <url value=\"http://npu-terminology.org\"/>",
+            "  <identifier>",
+            "  <version value=\"INT 2025-05-28\"/>",
+            "  <status value=\"active\"/>",
+            "  <content value=\"complete\"/>",
+            "  <property>",
+            "  <concept>",
+        ];
+        for pair in order.windows(2) {
+            assert!(
+                position(&xml, pair[0]) < position(&xml, pair[1]),
+                "{pair:?}"
+            );
+        }
+        assert!(xml.contains("    <code value=\"NPU01006\"/>\n"));
+        assert!(xml.contains("      <valueBoolean value=\"false\"/>\n"));
+
+        // The narrative is copied through as XHTML, not escaped.
+        let json = serde_json::to_value(&code_system).unwrap();
+        let div = json["text"]["div"].as_str().unwrap();
+        assert!(div.starts_with("<div xmlns=\"http://www.w3.org/1999/xhtml\" "));
+        assert!(div.contains("<table"));
+        assert!(xml.contains(&format!("    {}\n", div.trim())));
+        assert!(xml.ends_with("</CodeSystem>\n"));
+    }
+
+    #[test]
+    fn attributes_are_escaped() {
+        let xml = to_string_pretty(&json!({
+            "resourceType": "Basic",
+            "extension": [{ "url": "http://example.org/a?b&c", "valueString": "<\"x\">\ny" }],
+            "_implicitRules": { "id": "r" },
+        }))
+        .unwrap();
+        assert_eq!(
+            xml,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Basic xmlns=\"http://hl7.org/fhir\">\n\
+             \x20 <extension url=\"http://example.org/a?b&amp;c\">\n\
+             \x20   <valueString value=\"&lt;&quot;x&quot;&gt;&#xA;y\"/>\n\
+             \x20 </extension>\n\
+             \x20 <implicitRules id=\"r\"/>\n\
+             </Basic>\n"
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! Selection of the NPU concepts to convert, for building a subset CodeSystem
+//! such as the codes used by a single laboratory.
+//!
+//! A code is selected or dropped with all its rows, based on its current
+//! version, or its last row if it has none, so that the history of a selected
+//! code stays complete.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use clap::{Args, ValueEnum};
+use time::Date;
+use time::macros::format_description;
+
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreb, Npubegreber, parse_date, split_codes};
+
+#[derive(Args)]
+pub struct Filter {
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Only include codes of these specialties, e.g. CLC,CLP
+    pub specialty: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Exclude codes of these specialties
+    pub exclude_specialty: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Only include codes of these scale types, e.g. Ratio
+    pub scale_type: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Exclude codes of these scale types
+    pub exclude_scale_type: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Only include codes of these systems, e.g. Plasma
+    pub system: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', help_heading = "Filters")]
+    /// Exclude codes of these systems
+    pub exclude_system: Vec<String>,
+
+    #[arg(long, value_name = "FILE", help_heading = "Filters")]
+    /// Only include the codes listed in this file, separated by whitespace,
+    /// commas or semicolons; `#` starts a comment
+    pub codes: Option<String>,
+
+    #[arg(long, value_name = "FILE", help_heading = "Filters")]
+    /// Exclude the codes listed in this file
+    pub exclude_codes: Option<String>,
+
+    #[arg(long, value_name = "FIRST..LAST", help_heading = "Filters")]
+    /// Only include codes in this inclusive range, e.g. NPU01000..NPU01999;
+    /// may be repeated
+    pub code_range: Vec<CodeRange>,
+
+    #[arg(long, value_enum, help_heading = "Filters")]
+    /// Only include active or only retired codes
+    pub status: Option<Status>,
+
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_day, help_heading = "Filters")]
+    /// Only include codes whose change_date is on or after this day
+    pub changed_from: Option<Date>,
+
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_day, help_heading = "Filters")]
+    /// Only include codes whose change_date is on or before this day
+    pub changed_until: Option<Date>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Status {
+    Active,
+    Retired,
+}
+
+#[derive(Clone)]
+pub struct CodeRange {
+    first: String,
+    last: String,
+}
+
+impl FromStr for CodeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s
+            .split_once("..")
+            .ok_or_else(|| format!("expected FIRST..LAST, got {s:?}"))?;
+        let (first, last) = (first.trim(), last.trim());
+        if first.is_empty() || last.is_empty() {
+            return Err(format!("expected FIRST..LAST, got {s:?}"));
+        }
+        if code_key(first) > code_key(last) {
+            return Err(format!("{first} sorts after {last}"));
+        }
+        Ok(CodeRange {
+            first: first.to_owned(),
+            last: last.to_owned(),
+        })
+    }
+}
+
+impl CodeRange {
+    fn contains(&self, code: &str) -> bool {
+        let key = code_key(code);
+        code_key(&self.first) <= key && key <= code_key(&self.last)
+    }
+}
+
+/// Sort key of a code: the letter prefix, then the number, so that NPU999
+/// sorts before NPU1000.
+fn code_key(code: &str) -> (&str, u64, &str) {
+    let digits = code
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(code.len());
+    let (prefix, rest) = code.split_at(digits);
+    match rest.parse() {
+        Ok(number) => (prefix, number, ""),
+        Err(_) => (prefix, 0, rest),
+    }
+}
+
+pub fn parse_day(s: &str) -> Result<Date, time::error::Parse> {
+    Date::parse(s, format_description!("[year]-[month]-[day]"))
+}
+
+impl Filter {
+    /// Whether no filter is set, i.e. all codes are converted.
+    pub fn is_empty(&self) -> bool {
+        self.specialty.is_empty()
+            && self.exclude_specialty.is_empty()
+            && self.scale_type.is_empty()
+            && self.exclude_scale_type.is_empty()
+            && self.system.is_empty()
+            && self.exclude_system.is_empty()
+            && self.codes.is_none()
+            && self.exclude_codes.is_none()
+            && self.code_range.is_empty()
+            && self.status.is_none()
+            && self.changed_from.is_none()
+            && self.changed_until.is_none()
+    }
+
+    /// Keep the rows of the codes that match all filters.
+    pub fn apply(&self, data: Npubegreber) -> Result<Npubegreber, Error> {
+        if self.is_empty() {
+            return Ok(data);
+        }
+        let codes = self.codes.as_deref().map(read_codes).transpose()?;
+        let exclude_codes = self.exclude_codes.as_deref().map(read_codes).transpose()?;
+
+        // The row deciding whether a code is selected.
+        let mut deciding = HashMap::<&str, &Npubegreb>::new();
+        for npubegreb in &data.npubegreb {
+            let entry = deciding
+                .entry(npubegreb.npu_code.as_str())
+                .or_insert(npubegreb);
+            if !entry.is_current() {
+                *entry = npubegreb;
+            }
+        }
+        let selected: HashSet<String> = deciding
+            .into_iter()
+            .filter(|(code, npubegreb)| {
+                codes.as_ref().is_none_or(|codes| codes.contains(*code))
+                    && !exclude_codes
+                        .as_ref()
+                        .is_some_and(|codes| codes.contains(*code))
+                    && self.matches(npubegreb)
+            })
+            .map(|(code, _)| code.to_owned())
+            .collect();
+
+        let Npubegreber { text, npubegreb } = data;
+        Ok(Npubegreber {
+            text,
+            npubegreb: npubegreb
+                .into_iter()
+                .filter(|npubegreb| selected.contains(&npubegreb.npu_code))
+                .collect(),
+        })
+    }
+
+    fn matches(&self, npubegreb: &Npubegreb) -> bool {
+        let change_date = parse_date(&npubegreb.change_date).ok().map(|d| d.date());
+        includes(&self.specialty, &npubegreb.specialty)
+            && !matches_any(&self.exclude_specialty, &npubegreb.specialty)
+            && includes(&self.scale_type, &npubegreb.scale_type)
+            && !matches_any(&self.exclude_scale_type, &npubegreb.scale_type)
+            && includes(&self.system, &npubegreb.system)
+            && !matches_any(&self.exclude_system, &npubegreb.system)
+            && (self.code_range.is_empty()
+                || self
+                    .code_range
+                    .iter()
+                    .any(|range| range.contains(&npubegreb.npu_code)))
+            && self
+                .status
+                .is_none_or(|status| (status == Status::Active) == (npubegreb.active == "1"))
+            && self
+                .changed_from
+                .is_none_or(|from| change_date.is_some_and(|date| date >= from))
+            && self
+                .changed_until
+                .is_none_or(|until| change_date.is_some_and(|date| date <= until))
+    }
+}
+
+fn includes(values: &[String], value: &str) -> bool {
+    values.is_empty() || matches_any(values, value)
+}
+
+fn matches_any(values: &[String], value: &str) -> bool {
+    values
+        .iter()
+        .any(|candidate| candidate.trim().eq_ignore_ascii_case(value.trim()))
+}
+
+fn read_codes(path: &str) -> Result<HashSet<String>, Error> {
+    let contents = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(codes, _)| codes))
+        .flat_map(split_codes)
+        .map(str::to_owned)
+        .collect())
+}
+
+/// The filters that are set, for the description of a subset CodeSystem.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut criteria = Vec::new();
+        for (name, values) in [
+            ("specialty", &self.specialty),
+            ("not specialty", &self.exclude_specialty),
+            ("scale type", &self.scale_type),
+            ("not scale type", &self.exclude_scale_type),
+            ("system", &self.system),
+            ("not system", &self.exclude_system),
+        ] {
+            if !values.is_empty() {
+                criteria.push(format!("{name} {}", values.join(", ")));
+            }
+        }
+        if let Some(codes) = &self.codes {
+            criteria.push(format!("codes listed in {codes}"));
+        }
+        if let Some(codes) = &self.exclude_codes {
+            criteria.push(format!("codes not listed in {codes}"));
+        }
+        if !self.code_range.is_empty() {
+            let ranges: Vec<String> = self
+                .code_range
+                .iter()
+                .map(|range| format!("{} to {}", range.first, range.last))
+                .collect();
+            criteria.push(format!("codes {}", ranges.join(", ")));
+        }
+        match self.status {
+            Some(Status::Active) => criteria.push("active".to_owned()),
+            Some(Status::Retired) => criteria.push("retired".to_owned()),
+            None => {}
+        }
+        if let Some(from) = self.changed_from {
+            criteria.push(format!("changed on or after {from}"));
+        }
+        if let Some(until) = self.changed_until {
+            criteria.push(format!("changed on or before {until}"));
+        }
+        write!(f, "{}", criteria.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        filter: Filter,
+    }
+
+    fn filter(args: &[&str]) -> Filter {
+        Cli::try_parse_from(std::iter::once("npu-to-fhir").chain(args.iter().copied()))
+            .unwrap()
+            .filter
+    }
+
+    /// The code and current_version of the rows kept by `filter`.
+    fn rows(filter: &Filter, data: Npubegreber) -> Vec<(String, String)> {
+        filter
+            .apply(data)
+            .unwrap()
+            .npubegreb
+            .into_iter()
+            .map(|npubegreb| (npubegreb.npu_code, npubegreb.current_version))
+            .collect()
+    }
+
+    fn codes(filter: &Filter) -> Vec<String> {
+        let mut codes: Vec<String> = rows(filter, test_data())
+            .into_iter()
+            .map(|(code, _)| code)
+            .collect();
+        codes.dedup();
+        codes
+    }
+
+    #[test]
+    fn code_ranges_compare_the_number() {
+        let range: CodeRange = "NPU999..NPU1000".parse().unwrap();
+        assert!(range.contains("NPU999"));
+        assert!(range.contains("NPU1000"));
+        assert!(!range.contains("NPU998"));
+        assert!(!range.contains("NPU1001"));
+        assert!(!range.contains("DNK999"));
+
+        assert!("NPU1000..NPU999".parse::<CodeRange>().is_err());
+        assert!("NPU1000".parse::<CodeRange>().is_err());
+        assert!("..NPU1000".parse::<CodeRange>().is_err());
+    }
+
+    #[test]
+    fn code_ranges_can_be_repeated() {
+        let filter = filter(&[
+            "--code-range",
+            "NPU01003..NPU01005",
+            "--code-range",
+            " NPU03000 .. NPU09999 ",
+        ]);
+        assert_eq!(
+            codes(&filter),
+            ["NPU01003", "NPU01004", "NPU01005", "NPU03827"]
+        );
+    }
+
+    #[test]
+    fn current_version_decides_for_all_rows() {
+        // NPU01001 was active in its first version and is retired in its
+        // current one.
+        let retired = rows(&filter(&["--status", "retired"]), test_data());
+        assert!(retired.contains(&("NPU01001".to_owned(), "false".to_owned())));
+        assert!(retired.contains(&("NPU01001".to_owned(), "true".to_owned())));
+        let active = codes(&filter(&["--status", "active"]));
+        assert!(!active.contains(&"NPU01001".to_owned()));
+        assert!(active.contains(&"NPU01013".to_owned()));
+
+        let changed = codes(&filter(&["--changed-from", "2021-01-01"]));
+        assert_eq!(changed, ["NPU01001", "NPU01007", "NPU01012", "NPU63222"]);
+    }
+
+    #[test]
+    fn last_row_decides_without_a_current_version() {
+        let mut data = test_data();
+        for npubegreb in &mut data.npubegreb {
+            if npubegreb.npu_code == "NPU01001" {
+                npubegreb.current_version = "false".to_owned();
+            }
+        }
+        let retired = rows(&filter(&["--status", "retired"]), data);
+        assert_eq!(
+            retired
+                .iter()
+                .filter(|(code, _)| code == "NPU01001")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn listed_codes_and_exclusions() {
+        let path = std::env::temp_dir().join(format!("npu-filter-{}.txt", std::process::id()));
+        std::fs::write(&path, "NPU01003, NPU01004 # first\nNPU01005;NPU99999\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let listed = codes(&filter(&["--codes", &path, "--exclude-system", "blood"]));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(listed, ["NPU01003", "NPU01004"]);
+    }
+}
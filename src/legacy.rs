@@ -0,0 +1,75 @@
+//! Code tables of the current NPU concepts for legacy interfaces: an HL7 v2
+//! table for coding OBX-3 with the `NPU` coding system identifier, and a code
+//! list for CDA documents keyed by the NPU OID.
+
+use crate::code_system::{OID, V2_IDENTIFIER, VERSION, remove_duplicate_spaces};
+use crate::npu_concepts::{Npubegreber, split_codes};
+use crate::xml::escape;
+
+/// One line per concept with the code, the text, the CWE value for OBX-3, the
+/// status from HL7 table 0183 (A active, I inactive) and the replacing codes,
+/// separated by `|`. The text is escaped with the HL7 v2 escape sequences.
+pub fn v2_table(data: &Npubegreber) -> String {
+    let mut out = String::from("Code|Text|OBX-3|Status|Replaced by\n");
+    for npubegreb in data.current() {
+        let code = v2_escape(npubegreb.npu_code.trim());
+        let text = v2_escape(&remove_duplicate_spaces(&npubegreb.short_definition));
+        out.push_str(&format!(
+            "{code}|{text}|{code}^{text}^{V2_IDENTIFIER}|{}|{}\n",
+            if npubegreb.active == "1" { "A" } else { "I" },
+            split_codes(&npubegreb.replaced_by)
+                .collect::<Vec<_>>()
+                .join("~")
+        ));
+    }
+    out
+}
+
+/// An XML list with a `code` element per concept whose attributes are those
+/// of a CDA `CD` value, with the status and replacing codes of retired
+/// concepts.
+pub fn cda_code_list(data: &Npubegreber) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<codeList codeSystem=\"{OID}\" codeSystemName=\"{V2_IDENTIFIER}\" codeSystemVersion=\"{}\">\n",
+        escape(VERSION)
+    ));
+    for npubegreb in data.current() {
+        out.push_str(&format!(
+            "  <code code=\"{}\" codeSystem=\"{OID}\" codeSystemName=\"{V2_IDENTIFIER}\" displayName=\"{}\"",
+            escape(npubegreb.npu_code.trim()),
+            escape(&remove_duplicate_spaces(&npubegreb.short_definition))
+        ));
+        if npubegreb.active == "1" {
+            out.push_str(" status=\"active\"");
+        } else {
+            out.push_str(" status=\"retired\"");
+            let replaced_by: Vec<&str> = split_codes(&npubegreb.replaced_by).collect();
+            if !replaced_by.is_empty() {
+                out.push_str(&format!(
+                    " replacedBy=\"{}\"",
+                    escape(&replaced_by.join(" "))
+                ));
+            }
+        }
+        out.push_str("/>\n");
+    }
+    out.push_str("</codeList>\n");
+    out
+}
+
+/// `s` with the HL7 v2 delimiters replaced by their escape sequences.
+fn v2_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\E\\"),
+            '|' => escaped.push_str("\\F\\"),
+            '^' => escaped.push_str("\\S\\"),
+            '&' => escaped.push_str("\\T\\"),
+            '~' => escaped.push_str("\\R\\"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
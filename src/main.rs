@@ -1,355 +1,845 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, Write};
-use time::UtcDateTime;
-use time::UtcOffset;
-use time::macros::format_description;
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
 
+mod check;
+mod code_system;
+mod compose;
+mod definition;
+mod delta;
+mod error;
+mod fhir_xml;
+mod filter;
+mod legacy;
+mod merge;
+mod migrate;
+mod naming_system;
+mod narrative;
+mod ndjson;
 mod npu_concepts;
+mod observation_definition;
+mod profile;
+mod rdf;
+mod reader;
+mod search;
+mod site;
+mod sqlite;
+mod tabular;
+mod ucum;
+mod validate;
+mod watch;
+mod xml;
 
+use crate::code_system::{Content, Loss, build_code_system, concept_count};
+use crate::error::Error;
+use crate::narrative::Narrative;
 use crate::npu_concepts::Npubegreber;
 
-use fhir_sdk::r5::resources::CodeSystem;
-use fhir_sdk::r5::*;
-
 /// NPU to FHIR CodeSystem converter
 #[derive(Parser)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    after_help = "Exit codes: 0 success, 1 problems found by check, --validate, compose or \
+                  migrate-codes, 2 usage error, 3 I/O error, 4 XML or JSON parse error or \
+                  too many malformed records, 5 record validation error (--strict), \
+                  6 FHIR build error, 7 serialization error",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    #[arg(short, long, default_value = "npu_code_system.json")]
-    /// Output file for the FHIR CodeSystem JSON
-    output: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Path to the NPU XML file
-    input: String,
+    #[command(flatten)]
+    convert: ConvertArgs,
 }
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+
+#[derive(Args)]
+struct ConvertArgs {
+    #[arg(short, long)]
+    /// Output file [default: npu_code_system.<extension of the format>]
+    output: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = Format::Json)]
+    /// Output format
+    format: Format,
+
+    #[arg(long)]
+    /// Fail if a field value would be discarded or coerced, instead of
+    /// printing a summary of the dropped values
+    strict: bool,
+
+    #[arg(long)]
+    /// Validate the generated CodeSystem against the FHIR invariants and
+    /// write no output if there are errors
+    validate: bool,
+
+    #[arg(long)]
+    /// Write the validation result as a FHIR OperationOutcome JSON file
+    /// (implies --validate)
+    outcome: Option<String>,
+
+    #[arg(long)]
+    /// Fail if more than this many NPU records are malformed [default: skip
+    /// all malformed records]
+    max_errors: Option<usize>,
+
+    #[arg(long, value_enum)]
+    /// CodeSystem content mode [default: complete, or fragment if a filter or
+    /// delta is set]
+    content: Option<ContentMode>,
+
+    #[arg(long, default_value_t = 10)]
+    /// Number of concepts in an example CodeSystem
+    examples: usize,
+
+    #[arg(long, value_enum, default_value_t = NarrativeMode::Minimal)]
+    /// CodeSystem narrative
+    narrative: NarrativeMode,
+
+    #[arg(long, default_value_t = 100)]
+    /// Number of concepts per table in a narrative with concepts
+    narrative_page_size: usize,
+
+    #[arg(long)]
+    /// Regenerate the output whenever an input, --codes, --exclude-codes or
+    /// --previous file changes, until interrupted
+    watch: bool,
+
+    #[command(flatten)]
+    filter: filter::Filter,
+
+    #[command(flatten)]
+    delta: delta::Delta,
+
+    /// Paths to the NPU XML files; several files, e.g. a release and its
+    /// national additions or patches, are merged
+    #[arg(required = true)]
+    input: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report data-quality problems in the NPU XML file, exiting with a
+    /// nonzero status if any errors are found
+    Check {
+        #[arg(long)]
+        /// Print the report as JSON
+        json: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+    /// Search the current NPU concepts by free text, e.g. "plasma sodium",
+    /// or by code
+    ///
+    /// Unlike the other subcommands, search takes one NPU XML file before the
+    /// search terms; further files to merge with it are given with --merge.
+    Search {
+        #[arg(long)]
+        /// Only include concepts of this specialty
+        specialty: Option<String>,
+
+        #[arg(long)]
+        /// Only include concepts of this system
+        system: Option<String>,
+
+        #[arg(long)]
+        /// Only include concepts with this unit
+        unit: Option<String>,
+
+        #[arg(long)]
+        /// Only include concepts of this scale type
+        scale_type: Option<String>,
+
+        #[arg(short = 'n', long, default_value_t = 20)]
+        /// Maximum number of results
+        limit: usize,
+
+        #[arg(long)]
+        /// Print the results and facet counts as JSON
+        json: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        #[arg(long, value_name = "FILE")]
+        /// Another NPU XML file to merge with INPUT, e.g. national additions
+        /// or a patch; can be repeated
+        merge: Vec<String>,
+
+        /// Path to the NPU XML file; more files are merged with --merge
+        input: String,
+
+        /// Search terms
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Find the pre-coordinated NPU codes for a combination of axis values,
+    /// exiting with a nonzero status if there is none
+    Compose {
+        #[command(flatten)]
+        axes: compose::Axes,
+
+        #[arg(long, conflicts_with_all = [
+            "system", "sys_spec", "component", "comp_spec", "kind_of_property", "proc", "unit",
+        ])]
+        /// Take the axes from an NPU definition, e.g. "P—Sodium ion; subst.c. = ? mmol/L"
+        definition: Option<String>,
+
+        #[arg(long)]
+        /// Also match retired codes
+        include_retired: bool,
+
+        #[arg(long)]
+        /// Print the result as JSON
+        json: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+    /// Write a FHIR Bundle with an ObservationDefinition per active NPU code,
+    /// giving the permitted value types, UCUM unit and specimen
+    ObservationDefinitions {
+        #[arg(short, long, default_value = "npu_observation_definitions.json")]
+        /// Output file
+        output: String,
+
+        #[arg(long)]
+        /// Fail if a unit or scale type cannot be represented, instead of
+        /// printing a summary
+        strict: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+    /// Migrate the retired NPU codes in FHIR Observations to their current
+    /// replacements, exiting with a nonzero status if some codes cannot be
+    /// migrated
+    MigrateCodes {
+        #[arg(short, long)]
+        /// Output file [default: the observations file with .migrated before
+        /// the extension]
+        output: Option<String>,
+
+        #[arg(long)]
+        /// Change the codings with retired codes instead of adding a coding
+        /// with the current code
+        rewrite: bool,
+
+        #[arg(long)]
+        /// Read the observations as NDJSON, one resource per line [default:
+        /// if the file name ends with .ndjson]
+        ndjson: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+
+        /// Path to a FHIR Bundle or Observation JSON file, or an NDJSON file
+        /// of them
+        observations: String,
+    },
+    /// Write the FHIR NamingSystem of NPU, which maps the OID and the HL7 v2
+    /// identifier to the canonical URL
+    NamingSystem {
+        #[arg(short, long, default_value = "npu_naming_system.json")]
+        /// Output file
+        output: String,
+    },
+    /// Write a FHIR Bundle with a StructureDefinition profile on Observation
+    /// that binds the code to the NPU ValueSet, and that ValueSet
+    Profile {
+        #[arg(short, long, default_value = "npu_profile.json")]
+        /// Output file
+        output: String,
+
+        #[arg(long)]
+        /// Also write a derived profile and ValueSet per scale type, which
+        /// restrict the value to the data types of the scale
+        by_scale_type: bool,
+
+        #[arg(long)]
+        /// Fail if a scale type is unknown, instead of printing a summary
+        strict: bool,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+    /// Render the NPU XML file as a static HTML site with an index by
+    /// specialty and system, a page per code and a client-side search
+    Site {
+        #[arg(short, long, default_value = "npu_site")]
+        /// Output directory
+        output: String,
+
+        #[arg(long)]
+        /// Fail if more than this many NPU records are malformed
+        max_errors: Option<usize>,
+
+        /// Paths to the NPU XML files; several files are merged
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ContentMode {
+    /// All concepts
+    Complete,
+    /// No concepts, only the CodeSystem metadata
+    NotPresent,
+    /// The first --examples concepts, after filtering
+    Example,
+    /// The concepts selected by the filters and delta
+    Fragment,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NarrativeMode {
+    /// Only which concepts are included
+    Minimal,
+    /// Tables of the properties and the concept counts by specialty and status
+    Summary,
+    /// The summary and a table of the concepts, split into pages
+    Concepts,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// FHIR JSON
+    Json,
+    /// FHIR XML
+    Xml,
+    /// One FHIR CodeSystem.concept element per line
+    Ndjson,
+    /// One flat record per concept with all NPU fields and the earlier versions
+    NdjsonRecords,
+    /// Comma separated concepts with one column per CodeSystem property
+    Csv,
+    /// Tab separated concepts with one column per CodeSystem property
+    Tsv,
+    /// SQLite terminology database with a full-text index on the definitions
+    Sqlite,
+    /// SKOS concepts as RDF Turtle
+    Turtle,
+    /// SKOS concepts as RDF N-Triples
+    Ntriples,
+    /// HL7 v2 table with the OBX-3 coding and status of each concept
+    Hl7v2,
+    /// Code list for CDA documents with the OID, display and status of each
+    /// concept
+    Cda,
+}
+
+impl Format {
+    fn default_output(self) -> &'static str {
+        match self {
+            Format::Json => "npu_code_system.json",
+            Format::Xml => "npu_code_system.xml",
+            Format::Ndjson | Format::NdjsonRecords => "npu_code_system.ndjson",
+            Format::Csv => "npu_code_system.csv",
+            Format::Tsv => "npu_code_system.tsv",
+            Format::Sqlite => "npu_code_system.sqlite",
+            Format::Turtle => "npu_code_system.ttl",
+            Format::Ntriples => "npu_code_system.nt",
+            Format::Hl7v2 => "npu_code_system.hl7v2.txt",
+            Format::Cda => "npu_code_system.cda.xml",
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    let file = File::open(&cli.input)?;
-    let reader = BufReader::new(file);
-    let data: Npubegreber = serde_xml_rs::from_reader(reader)?;
-
-    let code_system = CodeSystem::builder()
-        .id("npu-terminology".to_owned())
-        .language("en-GB".to_owned())
-        .text(
-            types::Narrative::builder()
-                .status(codes::NarrativeStatus::Empty)
-                .div("<div xmlns=\"http://www.w3.org/1999/xhtml\" lang=\"en-GB\" xml:lang=\"en-GB\">Concepts not shown due to size of code system.</div>".to_owned())
-                .build()
-                .expect("Failed to build Narrative"),
-        )
-        .extension(vec![types::Extension::builder()
-            .url("http://hl7.org/fhir/StructureDefinition/codesystem-use-markdown".to_owned())
-            .value(
-                types::ExtensionValue::Boolean(false)
-            )
-            .build()
-            .expect("Failed to build Extension")])
-        .url("http://npu-terminology.org".to_owned())
-        .identifier(vec![Some(
-            types::Identifier::builder()
-                .system("urn:ietf:rfc:3986".to_owned())
-                .value("urn:oid:1.2.208.176.9.1".to_owned())
-                .build()
-                .expect("Failed to build Identifier"),
-        )])
-        .version("INT 2025-05-28".to_owned())
-        .name("NPUTerminology".to_owned())
-        .title("NPU Terminology Code System".to_owned())
-        .status(codes::PublicationStatus::Active)
-        .experimental(false)
-        .publisher("Danish Health Data Authority".to_owned())
-        .contact(vec![Some(
-            types::ContactDetail::builder()
-                .name("Danish National eHealth Authority".to_owned())
-                .telecom(vec![
-                    Some(
-                        types::ContactPoint::builder()
-                            .system(codes::ContactPointSystem::Url)
-                            .value("https://npu-terminology.org".to_owned())
-                            .build()
-                            .expect("Failed to build ContactPoint"),
-                    ),
-                    Some(
-                        types::ContactPoint::builder()
-                            .system(codes::ContactPointSystem::Email)
-                            .value("npu-terminology@sundhedsdata.dk".to_owned())
-                            .build()
-                            .expect("Failed to build ContactPoint"),
-                    ),
-                ])
-                .build()
-                .expect("Failed to build ContactDetail"),
-        )])
-        .case_sensitive(true)
-        .compositional(false)
-        .version_needed(false)
-        .content(codes::CodeSystemContentMode::Complete)
-        .value_set("http://npu-terminology.org/fhir/ValueSet/NPUFull".to_owned())
-        .description(format!("NPU Terminology International edition Code System. Generated from {} on {}",
-            cli.input,
-            UtcDateTime::now().format(format_description!("[year]-[month]-[day] [hour]:[minute]"))?
-        ))
-        .copyright("The ownership and intellectual property rights of NPU terminology are shared between the International Federation of Clinical Chemistry and Laboratory Medicine (IFCC) (www.ifcc.org) and the International Union of Pure and Applied Chemistry (IUPAC) (www.iupac.org).".to_owned())
-        .property(vec![
-            Some(resources::CodeSystemProperty::builder()
-                .code("created_date".to_owned())
-                .uri("http://npu-terminology.org/property#created_date".to_owned())
-                .description("The date when the NPU code was created".to_owned())
-                .r#type(codes::PropertyType::DateTime)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("change_date".to_owned())
-                .uri("http://npu-terminology.org/property#change_date".to_owned())
-                .description("The date when the NPU code was last changed".to_owned())
-                .r#type(codes::PropertyType::DateTime)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("status".to_owned())
-                .uri("http://hl7.org/fhir/concept-properties#status".to_owned())
-                .description("A code that indicates the status of the concept. Typical values are active, experimental, deprecated, and retired".to_owned())
-                .r#type(codes::PropertyType::Code)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("inactive".to_owned())
-                .uri("http://hl7.org/fhir/concept-properties#inactive".to_owned())
-                .description("True if the concept is not considered active - e.g. not a valid concept any more. Property type is boolean, default value is false".to_owned())
-                .r#type(codes::PropertyType::Boolean)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("system".to_owned())
-                .uri("http://npu-terminology.org/property#system".to_owned())
-                .description("The system of the the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()   
-                .code("sys_spec".to_owned())
-                .uri("http://npu-terminology.org/property#sys_spec".to_owned())
-                .description("The system specification of the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("component".to_owned())
-                .uri("http://npu-terminology.org/property#component".to_owned())
-                .description("The component of the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("comp_spec".to_owned())
-                .uri("http://npu-terminology.org/property#comp_spec".to_owned())
-                .description("The component specification of the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("kind_of_property".to_owned())
-                .uri("http://npu-terminology.org/property#kind_of_property".to_owned())
-                .description("The kind of property of the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("proc".to_owned())
-                .uri("http://npu-terminology.org/property#proc".to_owned())
-                .description("The procedure associated with the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("unit".to_owned())
-                .uri("http://npu-terminology.org/property#unit".to_owned())
-                .description("The unit of measurement for the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("specialty".to_owned())
-                .uri("http://npu-terminology.org/property#specialty".to_owned())
-                .description("The specialty associated with the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("context_dependent".to_owned())
-                .uri("http://npu-terminology.org/property#context_dependent".to_owned())
-                .description("Indicates if the NPU concept is context dependent".to_owned())
-                .r#type(codes::PropertyType::Boolean)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("scale_type".to_owned())
-                .uri("http://npu-terminology.org/property#scale_type".to_owned())
-                .description("The scale type of the NPU concept".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            Some(resources::CodeSystemProperty::builder()
-                .code("replaces".to_owned())
-                .uri("http://npu-terminology.org/property#replaces".to_owned())
-                .description("The retired NPU concept that this concept replaces".to_owned())
-                .r#type(codes::PropertyType::String)
-                .build()
-                .expect("Failed to build CodeSystemProperty")),
-            /* Some(resources::CodeSystemProperty::builder()
-                .code("current_version".to_owned())
-                .uri("http://npu-terminology.org/property#current_version".to_owned())
-                .description("Indicates if this is the current version of the NPU code".to_owned())
-                .r#type(codes::PropertyType::Boolean)
-                .build()
-                .expect("Failed to build CodeSystemProperty")), */
-        ]);
-
-    let mut concept = Vec::<Option<resources::CodeSystemConcept>>::new();
-
-    let mut count = 0;
-    for npubegreb in data.npubegreb {
-        if npubegreb.current_version != "true" {
-            continue;
+    match run(cli) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
         }
+    }
+}
 
-        count += 1;
-
-        concept.push(Some(
-            resources::CodeSystemConcept::builder()
-                .code(npubegreb.npu_code)
-                .display(npubegreb.short_definition)
-                .definition(remove_duplicate_spaces(
-                    &npubegreb.full_definition.replace("\n", ""),
-                ))
-                .property(
-                    vec![
-                        add_if_not_empty_date("created_date".to_owned(), npubegreb.created_date),
-                        add_if_not_empty_date("change_date".to_owned(), npubegreb.change_date),
-                        add_if_not_empty_code("status".to_owned(), if npubegreb.active == "1" { "active".to_owned() } else { "retired".to_owned() }),
-                        add_bool("inactive".to_owned(), npubegreb.active != "1"),
-                        add_if_not_empty_string("system".to_owned(), npubegreb.system),
-                        add_if_not_empty_string("sys_spec".to_owned(), npubegreb.sys_spec),
-                        add_if_not_empty_string("component".to_owned(), npubegreb.component),
-                        add_if_not_empty_string("comp_spec".to_owned(), npubegreb.comp_spec),
-                        add_if_not_empty_string(
-                            "kind_of_property".to_owned(),
-                            npubegreb.kind_of_property,
-                        ),
-                        add_if_not_empty_string("proc".to_owned(), npubegreb.proc),
-                        add_if_not_empty_string("unit".to_owned(), npubegreb.unit),
-                        add_if_not_empty_string("specialty".to_owned(), npubegreb.specialty),
-                        add_bool(
-                            "context_dependent".to_owned(),
-                            npubegreb.context_dependent == "Ja",
-                        ),
-                        add_if_not_empty_string("scale_type".to_owned(), npubegreb.scale_type),
-                        add_if_not_empty_string("replaces".to_owned(), npubegreb.replaces),
-                        /* add_bool(
-                            "current_version".to_owned(),
-                            npubegreb.current_version == "true",
-                        ), */
-                    ]
-                    .into_iter()
-                    .filter(Option::is_some)
-                    .collect(),
-                )
-                .build()
-                .expect("Failed to build CodeSystemConcept"),
-        ));
+fn run(cli: Cli) -> Result<ExitCode, Error> {
+    match cli.command {
+        Some(Command::Check {
+            json,
+            max_errors,
+            input,
+        }) => {
+            let (data, skipped) = read_inputs(&input, max_errors)?;
+            let report = check::check(&data);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+            Ok(if report.has_errors() || skipped > 0 {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            })
+        }
+        Some(Command::Search {
+            specialty,
+            system,
+            unit,
+            scale_type,
+            limit,
+            json,
+            max_errors,
+            merge,
+            input,
+            query,
+        }) => {
+            let inputs: Vec<String> = std::iter::once(input).chain(merge).collect();
+            let (data, _) = read_inputs(&inputs, max_errors)?;
+            let filters: Vec<(search::Facet, String)> = [
+                (search::Facet::Specialty, specialty),
+                (search::Facet::System, system),
+                (search::Facet::Unit, unit),
+                (search::Facet::ScaleType, scale_type),
+            ]
+            .into_iter()
+            .filter_map(|(facet, value)| Some((facet, value?)))
+            .collect();
+            let results = search::Index::new(&data).search(&query.join(" "), &filters, limit);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+            } else {
+                print_results(&results);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Compose {
+            axes,
+            definition,
+            include_retired,
+            json,
+            max_errors,
+            input,
+        }) => {
+            let axes = match definition {
+                Some(definition) => {
+                    compose::Axes::from_definition(&definition).unwrap_or_else(|e| {
+                        Cli::command()
+                            .error(
+                                ErrorKind::ValueValidation,
+                                format!("invalid --definition {definition:?}: {e}"),
+                            )
+                            .exit()
+                    })
+                }
+                None if axes.is_empty() => Cli::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "give at least one axis or --definition",
+                    )
+                    .exit(),
+                None => axes,
+            };
+            let (data, _) = read_inputs(&input, max_errors)?;
+            let composition = compose::compose(&data, &axes, include_retired);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&composition)?);
+            } else {
+                print_composition(&composition);
+            }
+            Ok(if composition.matches.is_empty() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            })
+        }
+        Some(Command::ObservationDefinitions {
+            output,
+            strict,
+            max_errors,
+            input,
+        }) => {
+            let (data, _) = read_inputs(&input, max_errors)?;
+            let mut losses = Vec::new();
+            let bundle = observation_definition::build_bundle(&data, &mut losses)?;
+            report_losses(&losses, strict)?;
+            write_output(&output, &serde_json::to_string_pretty(&bundle)?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::MigrateCodes {
+            output,
+            rewrite,
+            ndjson,
+            max_errors,
+            input,
+            observations,
+        }) => {
+            let (data, _) = read_inputs(&input, max_errors)?;
+            let json =
+                std::fs::read_to_string(&observations).map_err(|e| Error::io(&observations, e))?;
+            let mut report = migrate::Report::default();
+            let migrated = migrate::migrate(
+                &observations,
+                &json,
+                ndjson || observations.ends_with(".ndjson"),
+                &migrate::Replacements::new(&data),
+                rewrite,
+                &mut report,
+            )?;
+            let output = output.unwrap_or_else(|| migrate::default_output(&observations));
+            write_output(&output, &migrated)?;
+            print!("{report}");
+            Ok(if report.unresolvable.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Some(Command::NamingSystem { output }) => {
+            let naming_system = naming_system::build_naming_system()?;
+            write_output(&output, &serde_json::to_string_pretty(&naming_system)?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Profile {
+            output,
+            by_scale_type,
+            strict,
+            max_errors,
+            input,
+        }) => {
+            let (data, _) = read_inputs(&input, max_errors)?;
+            let mut losses = Vec::new();
+            let bundle = profile::build_bundle(&data, by_scale_type, &mut losses)?;
+            report_losses(&losses, strict)?;
+            write_output(&output, &serde_json::to_string_pretty(&bundle)?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Site {
+            output,
+            max_errors,
+            input,
+        }) => {
+            let (data, _) = read_inputs(&input, max_errors)?;
+            site::write(Path::new(&output), &data)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        None => convert(&cli.convert),
     }
+}
 
-    let code_system = code_system.count(count).concept(concept);
-    let code_system = code_system.build().expect("Failed to build CodeSystem");
-    let json =
-        serde_json::to_string_pretty(&code_system).expect("Failed to serialize CodeSystem to JSON");
+/// Read the NPU XML file, printing the malformed records that were skipped
+/// to stderr. Returns the data and the number of skipped records.
+fn read_input(input: &str, max_errors: Option<usize>) -> Result<(Npubegreber, usize), Error> {
+    let (data, errors) = reader::read(input, max_errors)?;
+    for error in &errors {
+        eprintln!("{input}: skipped malformed record at {error}");
+    }
+    if !errors.is_empty() {
+        eprintln!(
+            "{input}: skipped {} malformed records, read {}",
+            errors.len(),
+            data.npubegreb.len()
+        );
+    }
+    Ok((data, errors.len()))
+}
 
-    let mut output = File::create(&cli.output)?;
-    output.write_all(json.as_bytes())?;
+/// Read and merge several NPU XML files, printing the malformed records that
+/// were skipped and the conflicts between the files to stderr. Returns the
+/// data and the number of skipped records.
+fn read_inputs(
+    inputs: &[String],
+    max_errors: Option<usize>,
+) -> Result<(Npubegreber, usize), Error> {
+    let mut data = Vec::new();
+    let mut skipped = 0;
+    for input in inputs {
+        let (input_data, input_skipped) = read_input(input, max_errors)?;
+        data.push((input.clone(), input_data));
+        skipped += input_skipped;
+    }
+    if data.len() == 1 {
+        return Ok((data.remove(0).1, skipped));
+    }
+    let (merged, conflicts) = merge::merge(data)?;
+    for conflict in &conflicts {
+        eprintln!("{conflict}");
+    }
+    Ok((merged, skipped))
+}
 
-    Ok(())
+fn write_output(path: &str, contents: &str) -> Result<(), Error> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| Error::io(path, e))
 }
 
-fn remove_duplicate_spaces(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
-    let mut prev_space = false;
-    for c in s.chars() {
-        if c.is_whitespace() {
-            if !prev_space {
-                result.push(' ');
-                prev_space = true;
+fn convert(args: &ConvertArgs) -> Result<ExitCode, Error> {
+    if !args.watch {
+        return convert_once(args).map(|(exit_code, _)| exit_code);
+    }
+
+    let mut paths = args.input.clone();
+    for path in [
+        &args.filter.codes,
+        &args.filter.exclude_codes,
+        &args.delta.previous,
+    ] {
+        paths.extend(path.clone());
+    }
+    let mut watcher = watch::Watcher::new(paths);
+    let mut previous_count = None;
+    loop {
+        match convert_once(args) {
+            Ok((exit_code, count)) => {
+                let change = match previous_count {
+                    Some(previous) => format!(" ({:+})", i64::from(count) - i64::from(previous)),
+                    None => String::new(),
+                };
+                if exit_code == ExitCode::SUCCESS {
+                    eprintln!("converted {count} concepts{change}");
+                } else {
+                    eprintln!("{count} concepts{change} not written due to validation errors");
+                }
+                previous_count = Some(count);
             }
-        } else {
-            result.push(c);
-            prev_space = false;
+            Err(e) => eprintln!("error: {e}"),
         }
+        eprintln!("watching {} for changes", watcher.paths().join(", "));
+        watcher.wait();
     }
-    result.trim().to_string()
 }
 
-fn add_if_not_empty_string(
-    property: String,
-    str: String,
-) -> Option<resources::CodeSystemConceptProperty> {
-    if !str.is_empty() {
-        Some(
-            resources::CodeSystemConceptProperty::builder()
-                .code(property)
-                .value(resources::CodeSystemConceptPropertyValue::String(str))
-                .build()
-                .expect("Failed to build CodeSystemConceptProperty"),
-        )
-    } else {
-        None
+/// Convert once, returning the exit code and the number of concepts selected.
+fn convert_once(args: &ConvertArgs) -> Result<(ExitCode, u32), Error> {
+    let input = args.input.join(", ");
+    let output_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.format.default_output().to_owned());
+    let criteria: Vec<String> = [args.filter.to_string(), args.delta.to_string()]
+        .into_iter()
+        .filter(|criterion| !criterion.is_empty())
+        .collect();
+    let content = match (args.content, criteria.is_empty()) {
+        (None | Some(ContentMode::Complete), true) => Content::Complete,
+        (Some(ContentMode::NotPresent), true) => Content::NotPresent,
+        (Some(ContentMode::Example), _) => Content::Example(args.examples),
+        (None | Some(ContentMode::Fragment), false) => Content::Fragment {
+            criteria: criteria.join("; "),
+        },
+        (Some(ContentMode::Fragment), true) => Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--content fragment requires a filter, --since or --previous",
+            )
+            .exit(),
+        (Some(ContentMode::Complete | ContentMode::NotPresent), false) => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "filters, --since and --previous require --content fragment or example",
+            )
+            .exit(),
+    };
+    let (data, _) = read_inputs(&args.input, args.max_errors)?;
+    let total = concept_count(&data);
+    let previous = match &args.delta.previous {
+        Some(previous_path) => {
+            let (previous, _) = read_input(previous_path, args.max_errors)?;
+            let removed = delta::removed_codes(&previous, &data);
+            if !removed.is_empty() {
+                eprintln!(
+                    "{} codes of {previous_path} are not in {input} and are kept by servers \
+                     that apply the delta, first {}",
+                    removed.len(),
+                    removed[0]
+                );
+            }
+            Some(previous)
+        }
+        None => None,
+    };
+    let data = args.filter.apply(data)?;
+    let data = args.delta.apply(data, previous.as_ref())?;
+    let count = concept_count(&data);
+
+    let narrative = match args.narrative {
+        NarrativeMode::Minimal => Narrative::Minimal,
+        NarrativeMode::Summary => Narrative::Summary,
+        NarrativeMode::Concepts => Narrative::Concepts {
+            page_size: args.narrative_page_size,
+        },
+    };
+
+    let mut losses = Vec::new();
+    let code_system = build_code_system(&input, &data, &content, total, narrative, &mut losses)?;
+    report_losses(&losses, args.strict)?;
+
+    if args.validate || args.outcome.is_some() {
+        let issues = validate::validate(&code_system)?;
+        for issue in &issues {
+            eprintln!(
+                "{}: {}: {}",
+                issue.severity, issue.expression, issue.diagnostics
+            );
+        }
+        if let Some(outcome) = &args.outcome {
+            let json = serde_json::to_string_pretty(&validate::operation_outcome(&issues)?)?;
+            write_output(outcome, &json)?;
+        }
+        if issues
+            .iter()
+            .any(|issue| issue.severity == check::Severity::Error)
+        {
+            return Ok((ExitCode::FAILURE, count));
+        }
     }
+
+    let serialized = match args.format {
+        Format::Json => serde_json::to_string_pretty(&code_system)?,
+        Format::Xml => {
+            fhir_xml::to_string_pretty(&code_system).map_err(|e| Error::Serialization {
+                format: "FHIR XML",
+                message: e.to_string(),
+            })?
+        }
+        Format::Ndjson => ndjson::concepts(&code_system)?,
+        Format::NdjsonRecords => ndjson::records(&data)?,
+        Format::Csv => tabular::csv(&code_system)?,
+        Format::Tsv => tabular::tsv(&code_system)?,
+        Format::Turtle => rdf::turtle(&data),
+        Format::Ntriples => rdf::ntriples(&data),
+        Format::Hl7v2 => legacy::v2_table(&data),
+        Format::Cda => legacy::cda_code_list(&data),
+        Format::Sqlite => {
+            let mut losses = Vec::new();
+            sqlite::write(&output_path, &code_system, &data, &mut losses)?;
+            report_losses(&losses, args.strict)?;
+            return Ok((ExitCode::SUCCESS, count));
+        }
+    };
+
+    write_output(&output_path, &serialized)?;
+
+    Ok((ExitCode::SUCCESS, count))
 }
 
-fn add_if_not_empty_code(
-    property: String,
-    str: String,
-) -> Option<resources::CodeSystemConceptProperty> {
-    if !str.is_empty() {
-        Some(
-            resources::CodeSystemConceptProperty::builder()
-                .code(property)
-                .value(resources::CodeSystemConceptPropertyValue::Code(str))
-                .build()
-                .expect("Failed to build CodeSystemConceptProperty"),
-        )
-    } else {
-        None
+fn print_composition(composition: &compose::Composition) {
+    for found in &composition.matches {
+        println!(
+            "{}\t{}{}",
+            found.code,
+            found.display,
+            if found.active { "" } else { " (retired)" }
+        );
+    }
+    if composition.matches.is_empty() {
+        println!("No pre-coordinated NPU code has these axes");
+        if !composition.near_matches.is_empty() {
+            println!("Codes that differ in one axis:");
+        }
+        for near in &composition.near_matches {
+            println!(
+                "{}\t{}\t(differs in {})",
+                near.code,
+                near.display,
+                near.differs_in.join(", ")
+            );
+        }
     }
 }
 
-fn add_if_not_empty_date(
-    property: String,
-    str: String,
-) -> Option<resources::CodeSystemConceptProperty> {
-    UtcDateTime::parse(
-        &str,
-        format_description!("[year]-[month]-[day] [hour]:[minute]"),
-    )
-    .ok()
-    .map(|dt| {
-        resources::CodeSystemConceptProperty::builder()
-            .code(property)
-            .value(resources::CodeSystemConceptPropertyValue::DateTime(
-                fhir_sdk::DateTime::DateTime(dt.to_offset(UtcOffset::UTC).into()),
-            ))
-            .build()
-            .expect("Failed to build CodeSystemConceptProperty")
-    })
+fn print_results(results: &search::Results) {
+    for hit in &results.hits {
+        println!(
+            "{}\t{:.2}\t{}{}",
+            hit.code,
+            hit.score,
+            hit.display,
+            if hit.active { "" } else { " (retired)" }
+        );
+    }
+    println!("{} results", results.total);
+    for (facet, counts) in &results.facets {
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|(value, count)| format!("{value} {count}"))
+            .collect();
+        println!("{facet}: {}", counts.join(", "));
+    }
 }
 
-fn add_bool(property: String, b: bool) -> Option<resources::CodeSystemConceptProperty> {
-    Some(
-        resources::CodeSystemConceptProperty::builder()
-            .code(property)
-            .value(resources::CodeSystemConceptPropertyValue::Boolean(b))
-            .build()
-            .expect("Failed to build CodeSystemConceptProperty"),
-    )
+/// In strict mode, fail with every discarded or coerced value; otherwise print
+/// a summary per field to stderr.
+fn report_losses(losses: &[Loss], strict: bool) -> Result<(), Error> {
+    if losses.is_empty() {
+        return Ok(());
+    }
+
+    if strict {
+        for loss in losses {
+            eprintln!(
+                "{} {}: {:?} {}",
+                loss.npu_code, loss.field, loss.value, loss.reason
+            );
+        }
+        let first = &losses[0];
+        return Err(Error::RecordValidation {
+            npu_code: first.npu_code.clone(),
+            field: first.field.clone(),
+            message: format!(
+                "{:?} {}; {} field values would be discarded or coerced in total",
+                first.value,
+                first.reason,
+                losses.len()
+            ),
+        });
+    }
+
+    let mut summary = BTreeMap::<(&str, &str), Vec<&str>>::new();
+    for loss in losses {
+        summary
+            .entry((loss.field.as_str(), loss.reason))
+            .or_default()
+            .push(loss.npu_code.as_str());
+    }
+    eprintln!("{} field values were discarded or coerced:", losses.len());
+    for ((field, reason), codes) in summary {
+        eprintln!(
+            "  {field} ({reason}): {} values, first in {}",
+            codes.len(),
+            codes[0]
+        );
+    }
+    Ok(())
 }
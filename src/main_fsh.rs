@@ -1,12 +1,18 @@
 use clap::Parser;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, Write};
+use std::fmt::Write;
+use std::process::ExitCode;
 use time::macros::format_description;
 use time::{OffsetDateTime, UtcDateTime, format_description::well_known::Iso8601};
 
+// Shared with the FHIR converter, which uses all of them.
+#[allow(dead_code)]
+mod error;
+#[allow(dead_code)]
 mod npu_concepts;
+#[allow(dead_code)]
+mod reader;
 
-use crate::npu_concepts::Npubegreber;
+use crate::error::Error;
 
 /* use fhir_sdk::r5::resources::CodeSystem;
 use fhir_sdk::r5::*; */
@@ -18,20 +24,27 @@ struct Cli {
     /// Path to the NPU XML file
     input: String,
 }
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+const OUTPUT: &str = "npu.fsh";
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    let file = File::open(&cli.input)?;
-    let reader = BufReader::new(file);
-    let data: Npubegreber = serde_xml_rs::from_reader(reader)?;
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .append(false)
-        .open("npu.fsh")?;
+fn run(cli: &Cli) -> Result<(), Error> {
+    let (data, errors) = reader::read(&cli.input, None)?;
+    for error in &errors {
+        eprintln!("{}: skipped malformed record at {error}", cli.input);
+    }
 
+    let mut file = String::new();
     writeln!(file, "// NPU Terminology Code System")?;
     let now = OffsetDateTime::now_utc();
     writeln!(file, "// Generated from NPU XML data {}", cli.input)?;
@@ -72,7 +85,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let mut count = 0;
     for npubegreb in data.npubegreb {
-        if (npubegreb.active != "1") || (npubegreb.current_version != "true") {
+        // Read before the fields are moved into the properties.
+        let current = npubegreb.is_current();
+        if (npubegreb.active != "1") || !current {
             continue;
         }
 
@@ -84,8 +99,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "* #{} \"{}\" \"\"\"{}\"\"\"",
             npubegreb.npu_code, npubegreb.short_definition, remove_duplicate_spaces(&npubegreb.full_definition.replace("\n", ""))
         )?;
-        write!(file, "{}", add_if_not_empty_date("created_date".to_owned(), npubegreb.created_date))?;
-        write!(file, "{}", add_if_not_empty_date("changed_date".to_owned(), npubegreb.change_date))?;
+        write!(file, "{}", add_if_not_empty_date(&npubegreb.npu_code, "created_date".to_owned(), npubegreb.created_date)?)?;
+        write!(file, "{}", add_if_not_empty_date(&npubegreb.npu_code, "changed_date".to_owned(), npubegreb.change_date)?)?;
         write!(file, "{}", add_if_not_empty_string("system".to_owned(), npubegreb.system))?;
         write!(file, "{}", add_if_not_empty_string("sys_spec".to_owned(), npubegreb.sys_spec))?;
         write!(file, "{}", add_if_not_empty_string("component".to_owned(), npubegreb.component))?;
@@ -98,13 +113,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         write!(file, "{}", add_if_not_empty_string("scale_type".to_owned(), npubegreb.scale_type))?;
         write!(file, "{}", add_if_not_empty_string("replaces".to_owned(), npubegreb.replaces))?;
         write!(file, "{}", add_bool("active".to_owned(), npubegreb.active == "1"))?;
-        write!(file, "{}", add_bool("current_version".to_owned(), npubegreb.current_version == "true"))?;
+        write!(file, "{}", add_bool("current_version".to_owned(), current))?;
         
     
     }
 
     writeln!(file, "* ^count = {}", count)?;
-    Ok(())
+    std::fs::write(OUTPUT, file).map_err(|e| Error::io(OUTPUT, e))
 }
 
 fn add_if_not_empty_string(
@@ -122,19 +137,25 @@ fn add_if_not_empty_string(
     }
 }
 
-fn add_if_not_empty_date(property: String, str: String) -> String {
-    UtcDateTime::parse(
+fn add_if_not_empty_date(npu_code: &str, property: String, str: String) -> Result<String, Error> {
+    let Ok(dt) = UtcDateTime::parse(
         &str,
         format_description!("[year]-[month]-[day] [hour]:[minute]"),
-    )
-    .ok()
-    .map(|dt| {
-        "  * ^property[+].code = #".to_owned()
-            + &property
-            + "\n  * ^property[=].valueDateTime = \""
-            + &dt.format(&Iso8601::DATE_TIME_OFFSET).unwrap()
-            + "\"\n"
-    }).unwrap_or("".to_owned())
+    ) else {
+        return Ok(String::default());
+    };
+    let value = dt
+        .format(&Iso8601::DATE_TIME_OFFSET)
+        .map_err(|e| Error::RecordValidation {
+            npu_code: npu_code.to_owned(),
+            field: property.clone(),
+            message: e.to_string(),
+        })?;
+    Ok("  * ^property[+].code = #".to_owned()
+        + &property
+        + "\n  * ^property[=].valueDateTime = \""
+        + &value
+        + "\"\n")
 }
 
 fn add_bool(property: String, b: bool) -> String {
@@ -0,0 +1,238 @@
+//! Merging of several NPU XML files, e.g. the international release with
+//! national additions or monthly patches, into one `Npubegreber`.
+//!
+//! When more than one file has a current version of a code, the one with the
+//! latest `change_date` is kept, then the one with the latest
+//! `effective_from`, then the one from the file given last. Different current
+//! versions of a code are reported as conflicts. The earlier versions of all
+//! files are kept, without duplicates. Each row records the file it came from
+//! in `source`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use time::UtcDateTime;
+
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreb, Npubegreber, parse_date};
+
+/// Two files with different current versions of a code.
+pub struct Conflict {
+    pub npu_code: String,
+    pub kept: String,
+    pub discarded: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: current versions differ, kept {} over {} ({})",
+            self.npu_code, self.kept, self.discarded, self.reason
+        )
+    }
+}
+
+/// Merge the data of the `inputs`, in the order given on the command line.
+pub fn merge(inputs: Vec<(String, Npubegreber)>) -> Result<(Npubegreber, Vec<Conflict>), Error> {
+    // The current version of each code per input, the first if there are
+    // several.
+    let mut current = Vec::<HashMap<&str, &Npubegreb>>::new();
+    for (_, data) in &inputs {
+        let mut codes = HashMap::new();
+        for npubegreb in data.current() {
+            codes
+                .entry(npubegreb.npu_code.as_str())
+                .or_insert(npubegreb);
+        }
+        current.push(codes);
+    }
+    // The index of the input whose current version of each code is kept.
+    let mut winners = HashMap::<String, usize>::new();
+    let mut conflicts = Vec::new();
+    for (i, (path, data)) in inputs.iter().enumerate() {
+        for npubegreb in data.current() {
+            let Some(&kept) = winners.get(&npubegreb.npu_code) else {
+                winners.insert(npubegreb.npu_code.clone(), i);
+                continue;
+            };
+            let kept_path = &inputs[kept].0;
+            let Some(&earlier) = current[kept].get(npubegreb.npu_code.as_str()) else {
+                continue;
+            };
+            if serde_json::to_value(earlier)? == serde_json::to_value(npubegreb)? {
+                continue;
+            }
+            let (new, old) = (precedence(npubegreb), precedence(earlier));
+            let reason = if new.0 != old.0 {
+                "later change_date"
+            } else if new.1 != old.1 {
+                "later effective_from"
+            } else {
+                "same dates, file given later"
+            };
+            let (kept_path, discarded_path) = if new >= old {
+                winners.insert(npubegreb.npu_code.clone(), i);
+                (path, kept_path)
+            } else {
+                (kept_path, path)
+            };
+            conflicts.push(Conflict {
+                npu_code: npubegreb.npu_code.clone(),
+                kept: kept_path.clone(),
+                discarded: discarded_path.clone(),
+                reason,
+            });
+        }
+    }
+
+    let mut merged = Vec::new();
+    let mut history = HashSet::<String>::new();
+    for (i, (path, data)) in inputs.into_iter().enumerate() {
+        for mut npubegreb in data.npubegreb {
+            let keep = if npubegreb.is_current() {
+                winners.get(&npubegreb.npu_code) == Some(&i)
+            } else {
+                history.insert(serde_json::to_string(&npubegreb)?)
+            };
+            if keep {
+                npubegreb.source = Some(path.clone());
+                merged.push(npubegreb);
+            }
+        }
+    }
+
+    Ok((
+        Npubegreber {
+            text: None,
+            npubegreb: merged,
+        },
+        conflicts,
+    ))
+}
+
+/// The dates deciding which current version of a code is kept.
+fn precedence(npubegreb: &Npubegreb) -> (Option<UtcDateTime>, Option<UtcDateTime>) {
+    (
+        parse_date(&npubegreb.change_date).ok(),
+        parse_date(&npubegreb.effective_from).ok(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    fn row(npu_code: &str, change_date: &str, short_definition: &str) -> Npubegreb {
+        let mut npubegreb = test_data().npubegreb.remove(0);
+        npubegreb.npu_code = npu_code.to_owned();
+        npubegreb.change_date = change_date.to_owned();
+        npubegreb.short_definition = short_definition.to_owned();
+        npubegreb.current_version = "true".to_owned();
+        npubegreb
+    }
+
+    fn input(path: &str, npubegreb: Vec<Npubegreb>) -> (String, Npubegreber) {
+        (
+            path.to_owned(),
+            Npubegreber {
+                text: None,
+                npubegreb,
+            },
+        )
+    }
+
+    fn current<'a>(merged: &'a Npubegreber, npu_code: &str) -> Vec<&'a Npubegreb> {
+        merged
+            .current()
+            .filter(|npubegreb| npubegreb.npu_code == npu_code)
+            .collect()
+    }
+
+    #[test]
+    fn later_change_date_wins_a_conflict() {
+        for later_first in [false, true] {
+            let mut inputs = vec![
+                input(
+                    "release.xml",
+                    vec![row("NPU90001", "2020-01-01 00:00", "old")],
+                ),
+                input(
+                    "patch.xml",
+                    vec![row("NPU90001", "2024-01-01 00:00", "new")],
+                ),
+            ];
+            if later_first {
+                inputs.reverse();
+            }
+            let (merged, conflicts) = merge(inputs).unwrap();
+
+            let kept = current(&merged, "NPU90001");
+            assert_eq!(kept.len(), 1);
+            assert_eq!(kept[0].short_definition, "new");
+            assert_eq!(kept[0].source.as_deref(), Some("patch.xml"));
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].kept, "patch.xml");
+            assert_eq!(conflicts[0].discarded, "release.xml");
+            assert_eq!(conflicts[0].reason, "later change_date");
+        }
+    }
+
+    #[test]
+    fn file_given_later_wins_a_tie() {
+        let (merged, conflicts) = merge(vec![
+            input(
+                "release.xml",
+                vec![row("NPU90001", "2020-01-01 00:00", "first")],
+            ),
+            input(
+                "patch.xml",
+                vec![row("NPU90001", "2020-01-01 00:00", "second")],
+            ),
+        ])
+        .unwrap();
+
+        let kept = current(&merged, "NPU90001");
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].short_definition, "second");
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kept, "patch.xml");
+        assert_eq!(conflicts[0].reason, "same dates, file given later");
+    }
+
+    #[test]
+    fn added_codes_and_identical_rows_are_no_conflicts() {
+        let mut retired = row("NPU90001", "2010-01-01 00:00", "earlier");
+        retired.current_version = "false".to_owned();
+        let mut retired_again = row("NPU90001", "2010-01-01 00:00", "earlier");
+        retired_again.current_version = "false".to_owned();
+        let (merged, conflicts) = merge(vec![
+            input(
+                "release.xml",
+                vec![retired, row("NPU90001", "2020-01-01 00:00", "same")],
+            ),
+            input(
+                "national.xml",
+                vec![
+                    retired_again,
+                    row("NPU90001", "2020-01-01 00:00", "same"),
+                    row("DNK90002", "2024-01-01 00:00", "added"),
+                ],
+            ),
+        ])
+        .unwrap();
+
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.npubegreb.len(), 3);
+        assert_eq!(
+            current(&merged, "NPU90001")[0].source.as_deref(),
+            Some("release.xml")
+        );
+        assert_eq!(
+            current(&merged, "DNK90002")[0].source.as_deref(),
+            Some("national.xml")
+        );
+    }
+}
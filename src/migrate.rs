@@ -0,0 +1,405 @@
+//! Migration of the NPU codes in FHIR Observations to the current codes, for
+//! cleaning archives of results reported with codes that have since been
+//! retired.
+//!
+//! A retired code is followed through its `replaced_by` codes to an active
+//! code. The Observations are read from a JSON resource, usually a Bundle, or
+//! from NDJSON with one resource per line, and written in the same form.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::code_system::{OID, URL, remove_duplicate_spaces};
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreb, Npubegreber, split_codes};
+
+/// How a code in an Observation resolves to a current code.
+enum Resolution<'a> {
+    /// The code is active.
+    Current,
+    /// The code is retired and replaced by this active code.
+    Replaced { code: &'a str, display: String },
+    /// The code cannot be migrated, for this reason.
+    Unresolvable(&'static str),
+}
+
+/// The current versions of the NPU concepts by code.
+pub struct Replacements<'a> {
+    concepts: HashMap<&'a str, &'a Npubegreb>,
+}
+
+impl<'a> Replacements<'a> {
+    pub fn new(data: &'a Npubegreber) -> Self {
+        Replacements {
+            concepts: data
+                .current()
+                .map(|npubegreb| (npubegreb.npu_code.trim(), npubegreb))
+                .collect(),
+        }
+    }
+
+    fn resolve(&self, code: &str) -> Resolution<'a> {
+        let mut chain = vec![code];
+        loop {
+            let Some(npubegreb) = self.concepts.get(chain[chain.len() - 1]) else {
+                return Resolution::Unresolvable(if chain.len() == 1 {
+                    "unknown code"
+                } else {
+                    "replaced by an unknown code"
+                });
+            };
+            if npubegreb.active == "1" {
+                if chain.len() == 1 {
+                    return Resolution::Current;
+                }
+                return Resolution::Replaced {
+                    code: npubegreb.npu_code.trim(),
+                    display: remove_duplicate_spaces(&npubegreb.short_definition),
+                };
+            }
+            let replaced_by: Vec<&str> = split_codes(&npubegreb.replaced_by).collect();
+            match replaced_by[..] {
+                [] => return Resolution::Unresolvable("retired without replacement"),
+                [next] if chain.contains(&next) => {
+                    return Resolution::Unresolvable("cyclic replacement");
+                }
+                [next] => chain.push(next),
+                _ => return Resolution::Unresolvable("replaced by several codes"),
+            }
+        }
+    }
+}
+
+/// The codes migrated and the codes that could not be.
+#[derive(Default)]
+pub struct Report {
+    pub observations: usize,
+    /// The number of codings per retired code and its replacement.
+    pub migrated: BTreeMap<(String, String), usize>,
+    /// The number of codings per unresolvable code, with the reason.
+    pub unresolvable: BTreeMap<String, (&'static str, usize)>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((from, to), count) in &self.migrated {
+            writeln!(f, "{from} -> {to}: {count} codings")?;
+        }
+        for (code, (reason, count)) in &self.unresolvable {
+            writeln!(f, "{code}: {reason}, {count} codings not migrated")?;
+        }
+        writeln!(
+            f,
+            "{} observations, {} codings migrated, {} codes unresolvable",
+            self.observations,
+            self.migrated.values().sum::<usize>(),
+            self.unresolvable.len()
+        )
+    }
+}
+
+/// Migrate the Observations in `json`, the contents of `path`, which is NDJSON
+/// if `ndjson`. If `rewrite`, codings with retired codes are changed to the
+/// current code, otherwise a coding with the current code is added.
+pub fn migrate(
+    path: &str,
+    json: &str,
+    ndjson: bool,
+    replacements: &Replacements,
+    rewrite: bool,
+    report: &mut Report,
+) -> Result<String, Error> {
+    if !ndjson {
+        let mut resource =
+            serde_json::from_str::<Value>(json).map_err(|source| Error::JsonParse {
+                path: path.to_owned(),
+                line: None,
+                source,
+            })?;
+        migrate_resource(&mut resource, replacements, rewrite, report);
+        return Ok(serde_json::to_string_pretty(&resource)? + "\n");
+    }
+
+    let mut migrated = String::new();
+    for (i, line) in json.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut resource =
+            serde_json::from_str::<Value>(line).map_err(|source| Error::JsonParse {
+                path: path.to_owned(),
+                line: Some(i + 1),
+                source,
+            })?;
+        migrate_resource(&mut resource, replacements, rewrite, report);
+        migrated.push_str(&serde_json::to_string(&resource)?);
+        migrated.push('\n');
+    }
+    Ok(migrated)
+}
+
+/// The output file for the observations in `path` if none is given: `.migrated`
+/// before the extension, or at the end if there is none.
+pub fn default_output(path: &str) -> String {
+    let path = Path::new(path);
+    let extension = match path.extension() {
+        Some(extension) => format!("migrated.{}", extension.to_string_lossy()),
+        None => "migrated".to_owned(),
+    };
+    path.with_extension(extension)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Migrate an Observation, or the Observations in the entries of a Bundle.
+fn migrate_resource(
+    resource: &mut Value,
+    replacements: &Replacements,
+    rewrite: bool,
+    report: &mut Report,
+) {
+    match resource.get("resourceType").and_then(Value::as_str) {
+        Some("Bundle") => {
+            if let Some(entries) = resource.get_mut("entry").and_then(Value::as_array_mut) {
+                for entry in entries {
+                    if let Some(resource) = entry.get_mut("resource") {
+                        migrate_resource(resource, replacements, rewrite, report);
+                    }
+                }
+            }
+        }
+        Some("Observation") => {
+            report.observations += 1;
+            if let Some(codings) = resource
+                .pointer_mut("/code/coding")
+                .and_then(Value::as_array_mut)
+            {
+                migrate_codings(codings, replacements, rewrite, report);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn migrate_codings(
+    codings: &mut Vec<Value>,
+    replacements: &Replacements,
+    rewrite: bool,
+    report: &mut Report,
+) {
+    let mut added = Vec::new();
+    for coding in codings.iter_mut() {
+        let system = coding.get("system").and_then(Value::as_str);
+        if !system.is_some_and(is_npu_system) {
+            continue;
+        }
+        let Some(code) = coding.get("code").and_then(Value::as_str) else {
+            continue;
+        };
+        let code = code.trim().to_owned();
+        match replacements.resolve(&code) {
+            Resolution::Current => {}
+            Resolution::Replaced {
+                code: current,
+                display,
+            } => {
+                *report
+                    .migrated
+                    .entry((code, current.to_owned()))
+                    .or_default() += 1;
+                if rewrite {
+                    coding["code"] = json!(current);
+                    coding["display"] = json!(display);
+                    // The version of the retired code does not apply.
+                    if let Some(coding) = coding.as_object_mut() {
+                        coding.shift_remove("version");
+                    }
+                } else {
+                    added.push(json!({
+                        "system": URL,
+                        "code": current,
+                        "display": display,
+                    }));
+                }
+            }
+            Resolution::Unresolvable(reason) => {
+                report.unresolvable.entry(code).or_insert((reason, 0)).1 += 1;
+            }
+        }
+    }
+
+    for coding in added {
+        let present = codings.iter().any(|existing| {
+            existing.get("system") == coding.get("system")
+                && existing.get("code") == coding.get("code")
+        });
+        if !present {
+            codings.push(coding);
+        }
+    }
+}
+
+/// Whether `system` is the NPU canonical URL or OID.
+fn is_npu_system(system: &str) -> bool {
+    system == URL || system.strip_prefix("urn:oid:") == Some(OID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    fn row(npu_code: &str, active: bool, replaced_by: &str) -> Npubegreb {
+        let mut npubegreb = test_data().npubegreb.remove(0);
+        npubegreb.npu_code = npu_code.to_owned();
+        npubegreb.short_definition = format!("{npu_code} definition");
+        npubegreb.active = if active { "1" } else { "0" }.to_owned();
+        npubegreb.current_version = "true".to_owned();
+        npubegreb.replaced_by = replaced_by.to_owned();
+        npubegreb
+    }
+
+    /// NPU90001 is replaced by NPU90002, which is replaced by the active
+    /// NPU90003; NPU90004 and NPU90005 replace each other and NPU90006 has no
+    /// replacement.
+    fn data() -> Npubegreber {
+        Npubegreber {
+            text: None,
+            npubegreb: vec![
+                row("NPU90001", false, "NPU90002"),
+                row("NPU90002", false, "NPU90003"),
+                row("NPU90003", true, ""),
+                row("NPU90004", false, "NPU90005"),
+                row("NPU90005", false, "NPU90004"),
+                row("NPU90006", false, ""),
+            ],
+        }
+    }
+
+    fn observation(code: &str) -> Value {
+        json!({
+            "resourceType": "Observation",
+            "code": { "coding": [{ "system": URL, "version": "2010", "code": code }] },
+        })
+    }
+
+    fn codes(observation: &Value) -> Vec<&str> {
+        observation["code"]["coding"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|coding| coding["code"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn replacements_are_followed_to_an_active_code() {
+        let data = data();
+        let replacements = Replacements::new(&data);
+        assert!(matches!(
+            replacements.resolve("NPU90001"),
+            Resolution::Replaced {
+                code: "NPU90003",
+                ..
+            }
+        ));
+        assert!(matches!(
+            replacements.resolve("NPU90003"),
+            Resolution::Current
+        ));
+        assert!(matches!(
+            replacements.resolve("NPU90004"),
+            Resolution::Unresolvable("cyclic replacement")
+        ));
+        assert!(matches!(
+            replacements.resolve("NPU90006"),
+            Resolution::Unresolvable("retired without replacement")
+        ));
+        assert!(matches!(
+            replacements.resolve("NPU99999"),
+            Resolution::Unresolvable("unknown code")
+        ));
+    }
+
+    #[test]
+    fn ndjson_codings_are_rewritten() {
+        let data = data();
+        let ndjson = format!(
+            "{}\n\n{}\n",
+            observation("NPU90001"),
+            observation("NPU90006")
+        );
+        let mut report = Report::default();
+        let migrated = migrate(
+            "obs.ndjson",
+            &ndjson,
+            true,
+            &Replacements::new(&data),
+            true,
+            &mut report,
+        )
+        .unwrap();
+
+        let lines: Vec<Value> = migrated
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(codes(&lines[0]), ["NPU90003"]);
+        assert_eq!(
+            lines[0]["code"]["coding"][0]["display"],
+            "NPU90003 definition"
+        );
+        assert!(lines[0]["code"]["coding"][0].get("version").is_none());
+        assert_eq!(codes(&lines[1]), ["NPU90006"]);
+        assert_eq!(report.observations, 2);
+        assert_eq!(
+            report.migrated[&("NPU90001".to_owned(), "NPU90003".to_owned())],
+            1
+        );
+        assert_eq!(
+            report.unresolvable["NPU90006"],
+            ("retired without replacement", 1)
+        );
+    }
+
+    #[test]
+    fn bundle_codings_are_added() {
+        let data = data();
+        let bundle = json!({
+            "resourceType": "Bundle",
+            "entry": [
+                { "resource": observation("NPU90002") },
+                { "resource": observation("NPU90003") },
+            ],
+        });
+        let mut report = Report::default();
+        let migrated = migrate(
+            "obs.json",
+            &bundle.to_string(),
+            false,
+            &Replacements::new(&data),
+            false,
+            &mut report,
+        )
+        .unwrap();
+
+        let migrated: Value = serde_json::from_str(&migrated).unwrap();
+        let retired = &migrated["entry"][0]["resource"];
+        assert_eq!(codes(retired), ["NPU90002", "NPU90003"]);
+        assert_eq!(retired["code"]["coding"][0]["version"], "2010");
+        assert_eq!(codes(&migrated["entry"][1]["resource"]), ["NPU90003"]);
+        assert_eq!(report.observations, 2);
+        assert!(report.unresolvable.is_empty());
+    }
+
+    #[test]
+    fn default_output_is_next_to_the_observations() {
+        assert_eq!(default_output("dir/obs.json"), "dir/obs.migrated.json");
+        assert_eq!(default_output("obs.ndjson"), "obs.migrated.ndjson");
+        assert_eq!(default_output("obs"), "obs.migrated");
+    }
+}
@@ -0,0 +1,65 @@
+//! The NamingSystem of NPU, which lets servers resolve the OID and the HL7 v2
+//! identifier in legacy CDA and HL7 v2 messages to the canonical URL.
+
+use time::{UtcDateTime, UtcOffset};
+
+use crate::code_system::{OID, PUBLISHER, URL, V2_IDENTIFIER, VERSION, contact};
+use crate::error::{BuildContext, Error};
+
+use fhir_sdk::r5::resources::{NamingSystem, NamingSystemUniqueId};
+use fhir_sdk::r5::*;
+
+/// Build the NamingSystem with the unique ids of the NPU code system, of which
+/// the canonical URL is preferred.
+pub fn build_naming_system() -> Result<NamingSystem, Error> {
+    let mut unique_id = Vec::new();
+    for (r#type, value, preferred, comment) in [
+        (
+            codes::NamingSystemIdentifierType::Uri,
+            URL,
+            true,
+            "The canonical URL of the FHIR CodeSystem",
+        ),
+        (
+            codes::NamingSystemIdentifierType::Oid,
+            OID,
+            false,
+            "The OID used in CDA documents and HL7 v3 messages",
+        ),
+        (
+            codes::NamingSystemIdentifierType::V2Csmnemonic,
+            V2_IDENTIFIER,
+            false,
+            "The coding system identifier used in HL7 v2 messages, e.g. in OBX-3",
+        ),
+    ] {
+        unique_id.push(Some(
+            NamingSystemUniqueId::builder()
+                .r#type(r#type)
+                .value(value.to_owned())
+                .preferred(preferred)
+                .comment(comment.to_owned())
+                .build()
+                .context("NamingSystemUniqueId", None, Some(value))?,
+        ));
+    }
+
+    NamingSystem::builder()
+        .id("npu-terminology".to_owned())
+        .url("http://npu-terminology.org/fhir/NamingSystem/npu-terminology".to_owned())
+        .version(VERSION.to_owned())
+        .name("NPUTerminology".to_owned())
+        .title("NPU Terminology Naming System".to_owned())
+        .status(codes::PublicationStatus::Active)
+        .kind(codes::NamingSystemType::Codesystem)
+        .date(fhir_sdk::DateTime::DateTime(
+            UtcDateTime::now().to_offset(UtcOffset::UTC).into(),
+        ))
+        .publisher(PUBLISHER.to_owned())
+        .contact(contact()?)
+        .responsible("IFCC and IUPAC".to_owned())
+        .description("The identifiers of the NPU terminology code system".to_owned())
+        .unique_id(unique_id)
+        .build()
+        .context("NamingSystem", None, None)
+}
@@ -0,0 +1,238 @@
+//! The XHTML narrative of the CodeSystem.
+//!
+//! The generated narrative is built from the serialized properties and
+//! concepts, like the other derived outputs, and uses the `grid` table class
+//! that FHIR viewers style. The concept counts are taken from the NPU data
+//! instead, so that a CodeSystem without concepts still shows them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+use crate::code_system::Content;
+use crate::npu_concepts::Npubegreber;
+use crate::xml::escape;
+
+/// How much of the CodeSystem the narrative shows.
+#[derive(Clone, Copy)]
+pub enum Narrative {
+    /// Only which concepts the CodeSystem contains.
+    Minimal,
+    /// Tables of the properties and of the concept counts by specialty and
+    /// status.
+    Summary,
+    /// The summary and the concepts, in tables of at most `page_size` rows.
+    Concepts { page_size: usize },
+}
+
+/// Whether the narrative represents the CodeSystem, i.e. has status
+/// `generated` rather than `empty`.
+pub fn is_generated(narrative: Narrative, content: &Content) -> bool {
+    !matches!(
+        (narrative, content),
+        (Narrative::Minimal, Content::Complete)
+    )
+}
+
+/// The narrative `div` for the serialized `properties` and `concepts` of the
+/// CodeSystem, and the current concepts of `data` it was built from. `total`
+/// is the number of concepts of the complete code system.
+pub fn div(
+    narrative: Narrative,
+    content: &Content,
+    properties: &Value,
+    concepts: &Value,
+    data: &Npubegreber,
+    total: u32,
+) -> String {
+    let concepts = concepts.as_array().map(Vec::as_slice).unwrap_or_default();
+
+    let introduction = introduction(narrative, content, concepts.len(), total);
+    let mut xhtml = String::new();
+    if matches!(narrative, Narrative::Minimal) {
+        xhtml.push_str(&introduction);
+    } else {
+        xhtml.push_str(&format!("<p>{introduction}</p>"));
+        push_properties(&mut xhtml, properties);
+        if data.current().next().is_some() {
+            push_counts(&mut xhtml, data);
+        }
+    }
+    if let Narrative::Concepts { page_size } = narrative
+        && !concepts.is_empty()
+    {
+        push_concepts(&mut xhtml, concepts, page_size.max(1));
+    }
+
+    format!(
+        "<div xmlns=\"http://www.w3.org/1999/xhtml\" lang=\"en-GB\" xml:lang=\"en-GB\">{xhtml}</div>"
+    )
+}
+
+fn introduction(narrative: Narrative, content: &Content, concepts: usize, total: u32) -> String {
+    match content {
+        Content::Complete => match narrative {
+            Narrative::Minimal => "Concepts not shown due to size of code system.".to_owned(),
+            _ => format!("Contains all {total} concepts of the code system."),
+        },
+        Content::NotPresent => format!(
+            "Concepts are not included in this resource. The complete code system has {total} concepts."
+        ),
+        Content::Example(_) => format!(
+            "Contains {concepts} example concepts of the {total} concepts of the complete code system."
+        ),
+        Content::Fragment { criteria } => format!(
+            "Contains {concepts} of the {total} concepts of the complete code system, selected by: {}.",
+            escape(criteria)
+        ),
+    }
+}
+
+fn push_properties(xhtml: &mut String, properties: &Value) {
+    xhtml.push_str("<h3>Properties</h3>");
+    xhtml.push_str("<table class=\"grid\"><tr><th>Code</th><th>Type</th><th>Description</th></tr>");
+    for property in properties.as_array().into_iter().flatten() {
+        xhtml.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(string(property, "code")),
+            escape(string(property, "type")),
+            escape(string(property, "description"))
+        ));
+    }
+    xhtml.push_str("</table>");
+}
+
+/// Current concepts per specialty and status, with a total row and column.
+fn push_counts(xhtml: &mut String, data: &Npubegreber) {
+    let mut counts = BTreeMap::<&str, BTreeMap<&str, usize>>::new();
+    let mut statuses = BTreeSet::<&str>::new();
+    let mut total = 0;
+    for npubegreb in data.current() {
+        let specialty = match npubegreb.specialty.as_str() {
+            "" => "(none)",
+            specialty => specialty,
+        };
+        let status = if npubegreb.active == "1" {
+            "active"
+        } else {
+            "retired"
+        };
+        total += 1;
+        statuses.insert(status);
+        *counts
+            .entry(specialty)
+            .or_default()
+            .entry(status)
+            .or_default() += 1;
+    }
+
+    xhtml.push_str("<h3>Concepts by specialty and status</h3>");
+    xhtml.push_str("<table class=\"grid\"><tr><th>Specialty</th>");
+    for status in &statuses {
+        xhtml.push_str(&format!("<th>{}</th>", escape(status)));
+    }
+    xhtml.push_str("<th>Total</th></tr>");
+    for (specialty, by_status) in &counts {
+        xhtml.push_str(&format!("<tr><td>{}</td>", escape(specialty)));
+        for status in &statuses {
+            xhtml.push_str(&format!(
+                "<td>{}</td>",
+                by_status.get(status).copied().unwrap_or(0)
+            ));
+        }
+        xhtml.push_str(&format!(
+            "<td>{}</td></tr>",
+            by_status.values().sum::<usize>()
+        ));
+    }
+    xhtml.push_str("<tr><td><b>Total</b></td>");
+    for status in &statuses {
+        let count: usize = counts
+            .values()
+            .filter_map(|by_status| by_status.get(status))
+            .sum();
+        xhtml.push_str(&format!("<td><b>{count}</b></td>"));
+    }
+    xhtml.push_str(&format!("<td><b>{total}</b></td></tr></table>"));
+}
+
+/// The concepts in tables of `page_size` rows, linked from a page index.
+fn push_concepts(xhtml: &mut String, concepts: &[Value], page_size: usize) {
+    xhtml.push_str("<h3>Concepts</h3>");
+    let pages = concepts.len().div_ceil(page_size);
+    if pages > 1 {
+        xhtml.push_str("<p>Pages:");
+        for page in 1..=pages {
+            xhtml.push_str(&format!(" <a href=\"#concepts-{page}\">{page}</a>"));
+        }
+        xhtml.push_str("</p>");
+    }
+    for (i, page) in concepts.chunks(page_size).enumerate() {
+        let first = i * page_size + 1;
+        if pages > 1 {
+            xhtml.push_str(&format!(
+                "<h4 id=\"concepts-{}\">Concepts {first}\u{2013}{}</h4>",
+                i + 1,
+                first + page.len() - 1
+            ));
+        }
+        xhtml.push_str(
+            "<table class=\"grid\"><tr><th>Code</th><th>Display</th><th>Unit</th><th>Status</th></tr>",
+        );
+        for concept in page {
+            xhtml.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(string(concept, "code")),
+                escape(string(concept, "display")),
+                escape(property(concept, "unit").unwrap_or_default()),
+                escape(property(concept, "status").unwrap_or_default())
+            ));
+        }
+        xhtml.push_str("</table>");
+    }
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// The string or code value of the concept property `code`.
+fn property<'a>(concept: &'a Value, code: &str) -> Option<&'a str> {
+    concept
+        .get("property")?
+        .as_array()?
+        .iter()
+        .find(|property| property.get("code").and_then(Value::as_str) == Some(code))?
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.starts_with("value"))?
+        .1
+        .as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+    use serde_json::json;
+
+    #[test]
+    fn summary_without_concepts_counts_the_data() {
+        let div = div(
+            Narrative::Summary,
+            &Content::NotPresent,
+            &json!([]),
+            &json!([]),
+            &test_data(),
+            15,
+        );
+        assert!(div.contains("The complete code system has 15 concepts."));
+        assert!(
+            div.contains(
+                "<tr><th>Specialty</th><th>active</th><th>retired</th><th>Total</th></tr>"
+            )
+        );
+        assert!(div.contains("<tr><td>CLP</td><td>3</td><td>4</td><td>7</td></tr>"));
+        assert!(div.ends_with("<td><b>15</b></td></tr></table></div>"));
+    }
+}
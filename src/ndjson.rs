@@ -0,0 +1,57 @@
+//! NDJSON export, one JSON object per line, for bulk loading into analytics
+//! tools without parsing the whole CodeSystem document.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::npu_concepts::{Npubegreb, Npubegreber};
+
+/// One line per `CodeSystem.concept` element of the serialized CodeSystem.
+pub fn concepts<T: Serialize>(code_system: &T) -> Result<String, serde_json::Error> {
+    let value = serde_json::to_value(code_system)?;
+    let mut ndjson = String::new();
+    if let Some(concepts) = value.get("concept").and_then(Value::as_array) {
+        for concept in concepts {
+            ndjson.push_str(&serde_json::to_string(concept)?);
+            ndjson.push('\n');
+        }
+    }
+    Ok(ndjson)
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    current: &'a Npubegreb,
+    /// The earlier versions of the concept, in source order.
+    history: Vec<&'a Npubegreb>,
+}
+
+/// One line per current `Npubegreb` with all its fields, including the short
+/// forms, and the earlier versions of the same NPU code.
+pub fn records(data: &Npubegreber) -> Result<String, serde_json::Error> {
+    let mut history = HashMap::<&str, Vec<&Npubegreb>>::new();
+    for npubegreb in &data.npubegreb {
+        if !npubegreb.is_current() {
+            history
+                .entry(npubegreb.npu_code.as_str())
+                .or_default()
+                .push(npubegreb);
+        }
+    }
+
+    let mut ndjson = String::new();
+    for npubegreb in data.current() {
+        let record = Record {
+            current: npubegreb,
+            history: history
+                .remove(npubegreb.npu_code.as_str())
+                .unwrap_or_default(),
+        };
+        ndjson.push_str(&serde_json::to_string(&record)?);
+        ndjson.push('\n');
+    }
+    Ok(ndjson)
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+use time::macros::format_description;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Npubegreber {
@@ -10,7 +12,7 @@ pub struct Npubegreber {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Npubegreb {
-    #[serde(rename = "$text")]
+    #[serde(rename = "$text", skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
     pub created_date: String,
     pub change_date: String,
@@ -42,5 +44,45 @@ pub struct Npubegreb {
     pub effective_to: String,
     pub active: String,
     pub current_version: String,
+    /// The file the row was read from, if several were merged.
+    #[serde(skip)]
+    pub source: Option<String>,
+}
+
+impl Npubegreber {
+    /// The rows with the current version of each code.
+    pub fn current(&self) -> impl Iterator<Item = &Npubegreb> {
+        self.npubegreb
+            .iter()
+            .filter(|npubegreb| npubegreb.is_current())
+    }
+}
+
+impl Npubegreb {
+    /// Whether the row is the current version of its code.
+    pub fn is_current(&self) -> bool {
+        self.current_version == "true"
+    }
 }
 
+/// The NPU codes in a `replaces` or `replaced_by` field.
+pub fn split_codes(field: &str) -> impl Iterator<Item = &str> {
+    field
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|code| !code.is_empty())
+}
+
+/// Parse a date field of the NPU XML, e.g. `1996-01-01 01:00`.
+pub fn parse_date(s: &str) -> Result<UtcDateTime, time::error::Parse> {
+    UtcDateTime::parse(
+        s,
+        format_description!("[year]-[month]-[day] [hour]:[minute]"),
+    )
+}
+
+/// The rows of `testdata/npu_test_data.xml`.
+#[cfg(test)]
+pub fn test_data() -> Npubegreber {
+    serde_xml_rs::from_str(include_str!("../testdata/npu_test_data.xml"))
+        .expect("the test data is valid NPU XML")
+}
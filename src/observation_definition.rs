@@ -0,0 +1,136 @@
+//! One FHIR `ObservationDefinition` per active NPU code, so that the
+//! Observations a laboratory system reports with the code can be checked for
+//! the value type and unit.
+//!
+//! The scale type gives the permitted data types: ratio and difference scales
+//! have Quantity values in the UCUM equivalent of the NPU unit, ordinal and
+//! nominal scales CodeableConcept or string values. The system of the code is
+//! the specimen, as a reference with only a display since NPU has no
+//! SpecimenDefinitions.
+
+use crate::code_system::{Loss, URL, VERSION, remove_duplicate_spaces};
+use crate::definition::axis_text;
+use crate::error::{BuildContext, Error};
+use crate::npu_concepts::{Npubegreb, Npubegreber};
+use crate::ucum;
+
+use fhir_sdk::r5::resources::{Bundle, BundleEntry, ObservationDefinition, Resource};
+use fhir_sdk::r5::*;
+
+/// The base of the canonical URLs of the ObservationDefinitions.
+const BASE_URL: &str = "http://npu-terminology.org/fhir/ObservationDefinition";
+
+/// A collection Bundle with the ObservationDefinitions of the current, active
+/// codes in `data`. Units and scale types that cannot be represented are added
+/// to `losses`.
+pub fn build_bundle(data: &Npubegreber, losses: &mut Vec<Loss>) -> Result<Bundle, Error> {
+    let mut entry = Vec::new();
+    for npubegreb in data.current() {
+        if npubegreb.active != "1" {
+            continue;
+        }
+        let code = npubegreb.npu_code.as_str();
+        entry.push(Some(
+            BundleEntry::builder()
+                .full_url(format!("{BASE_URL}/{code}"))
+                .resource(Resource::ObservationDefinition(build(npubegreb, losses)?))
+                .build()
+                .context("BundleEntry", Some(code), None)?,
+        ));
+    }
+
+    Bundle::builder()
+        .r#type(codes::BundleType::Collection)
+        .entry(entry)
+        .build()
+        .context("Bundle", None, None)
+}
+
+/// The value types of observations on the NPU `scale_type`, or `None` if the
+/// scale type is unknown.
+pub fn data_types(scale_type: &str) -> Option<Vec<codes::ObservationDataType>> {
+    match scale_type.trim() {
+        "Ratio" | "Difference" => Some(vec![codes::ObservationDataType::Quantity]),
+        "Ordinal" | "Nominal" => Some(vec![
+            codes::ObservationDataType::CodeableConcept,
+            codes::ObservationDataType::String,
+        ]),
+        _ => None,
+    }
+}
+
+fn build(npubegreb: &Npubegreb, losses: &mut Vec<Loss>) -> Result<ObservationDefinition, Error> {
+    let code = npubegreb.npu_code.as_str();
+    let display = remove_duplicate_spaces(&npubegreb.short_definition);
+
+    let permitted_data_type = data_types(&npubegreb.scale_type).unwrap_or_else(|| {
+        losses.push(Loss::new(
+            code,
+            "scale_type",
+            &npubegreb.scale_type,
+            "unknown scale type, no permittedDataType",
+        ));
+        Vec::new()
+    });
+
+    let mut permitted_unit = Vec::new();
+    let unit = remove_duplicate_spaces(&npubegreb.unit);
+    if permitted_data_type.contains(&codes::ObservationDataType::Quantity) && !unit.is_empty() {
+        match ucum::from_npu(&unit) {
+            Some(ucum_code) => permitted_unit.push(Some(
+                types::Coding::builder()
+                    .system(ucum::SYSTEM.to_owned())
+                    .code(ucum_code)
+                    .display(unit)
+                    .build()
+                    .context("Coding", Some(code), Some("unit"))?,
+            )),
+            None => losses.push(Loss::new(
+                code,
+                "unit",
+                &npubegreb.unit,
+                "no UCUM equivalent, no permittedUnit",
+            )),
+        }
+    }
+
+    let specimen = axis_text(
+        &remove_duplicate_spaces(&npubegreb.system),
+        &remove_duplicate_spaces(&npubegreb.sys_spec),
+    );
+
+    ObservationDefinition::builder()
+        .id(code.to_owned())
+        .url(format!("{BASE_URL}/{code}"))
+        .version(VERSION.to_owned())
+        .name(code.to_owned())
+        .title(display.clone())
+        .status(codes::PublicationStatus::Active)
+        .code(
+            types::CodeableConcept::builder()
+                .coding(vec![Some(
+                    types::Coding::builder()
+                        .system(URL.to_owned())
+                        .code(code.to_owned())
+                        .display(display)
+                        .build()
+                        .context("Coding", Some(code), None)?,
+                )])
+                .build()
+                .context("CodeableConcept", Some(code), None)?,
+        )
+        .permitted_data_type(permitted_data_type.into_iter().map(Some).collect())
+        .permitted_unit(permitted_unit)
+        .specimen(if specimen.is_empty() {
+            Vec::new()
+        } else {
+            vec![Some(
+                types::Reference::builder()
+                    .display(specimen)
+                    .build()
+                    .context("Reference", Some(code), Some("system"))?,
+            )]
+        })
+        .build()
+        .context("ObservationDefinition", Some(code), None)
+}
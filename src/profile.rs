@@ -0,0 +1,228 @@
+//! A StructureDefinition profile on Observation for NPU laboratory results,
+//! with the ValueSets it binds `Observation.code` to.
+//!
+//! The base profile requires a code from the NPU ValueSet. Per scale type, a
+//! derived profile can additionally bind the code to the NPU codes of that
+//! scale type, selected by a filter on the `scale_type` property, and
+//! restrict `Observation.value[x]` to the data types of the scale.
+
+use std::collections::BTreeMap;
+
+use crate::code_system::{Loss, URL, VERSION};
+use crate::error::{BuildContext, Error};
+use crate::npu_concepts::Npubegreber;
+use crate::observation_definition::data_types;
+
+use fhir_sdk::r5::resources::{
+    Bundle, BundleEntry, Resource, StructureDefinition, StructureDefinitionDifferential, ValueSet,
+    ValueSetCompose, ValueSetComposeInclude, ValueSetComposeIncludeFilter,
+};
+use fhir_sdk::r5::*;
+
+const BASE_URL: &str = "http://npu-terminology.org/fhir";
+/// The ValueSet with all NPU codes, which is also the CodeSystem `valueSet`.
+const FULL_VALUE_SET: &str = "NPUFull";
+const PROFILE_ID: &str = "npu-lab-result";
+const PROFILE_NAME: &str = "NPULabResult";
+
+/// A collection Bundle with the NPU lab result profile and the NPU ValueSet
+/// and, if `by_scale_type`, a profile and ValueSet per scale type of the
+/// current, active codes in `data`. Unknown scale types are added to
+/// `losses`.
+pub fn build_bundle(
+    data: &Npubegreber,
+    by_scale_type: bool,
+    losses: &mut Vec<Loss>,
+) -> Result<Bundle, Error> {
+    let mut resources = vec![
+        profile(
+            PROFILE_ID,
+            PROFILE_NAME,
+            "NPU Laboratory Result",
+            "An Observation coded with an NPU code.",
+            "http://hl7.org/fhir/StructureDefinition/Observation",
+            FULL_VALUE_SET,
+            &[],
+        )?,
+        value_set(
+            FULL_VALUE_SET,
+            "NPU Terminology",
+            "All codes of the NPU terminology.",
+            None,
+        )?,
+    ];
+
+    if by_scale_type {
+        // The scale types with the first code of each, for reporting unknown ones.
+        let mut scale_types = BTreeMap::<&str, &str>::new();
+        for npubegreb in data.current().filter(|npubegreb| npubegreb.active == "1") {
+            scale_types
+                .entry(npubegreb.scale_type.trim())
+                .or_insert(&npubegreb.npu_code);
+        }
+        for (scale_type, code) in scale_types {
+            let Some(data_types) = data_types(scale_type) else {
+                losses.push(Loss::new(
+                    code,
+                    "scale_type",
+                    scale_type,
+                    "unknown scale type, no profile",
+                ));
+                continue;
+            };
+            let value_set_name = format!("NPU{scale_type}");
+            resources.push(profile(
+                &format!("{PROFILE_ID}-{}", scale_type.to_lowercase()),
+                &format!("{PROFILE_NAME}{scale_type}"),
+                &format!("NPU Laboratory Result, {scale_type} Scale"),
+                &format!("An Observation coded with an NPU code of the {scale_type} scale type."),
+                &format!("{BASE_URL}/StructureDefinition/{PROFILE_ID}"),
+                &value_set_name,
+                &data_types,
+            )?);
+            resources.push(value_set(
+                &value_set_name,
+                &format!("NPU Terminology, {scale_type} Scale"),
+                &format!("The NPU codes of the {scale_type} scale type."),
+                Some(scale_type),
+            )?);
+        }
+    }
+
+    let mut entry = Vec::new();
+    for (full_url, resource) in resources {
+        entry.push(Some(
+            BundleEntry::builder()
+                .full_url(full_url)
+                .resource(resource)
+                .build()
+                .context("BundleEntry", None, None)?,
+        ));
+    }
+
+    Bundle::builder()
+        .r#type(codes::BundleType::Collection)
+        .entry(entry)
+        .build()
+        .context("Bundle", None, None)
+}
+
+/// The canonical URL and resource of a profile with `Observation.code` bound
+/// to the ValueSet `value_set` and, unless `value_types` is empty,
+/// `Observation.value[x]` restricted to them.
+fn profile(
+    id: &str,
+    name: &str,
+    title: &str,
+    description: &str,
+    base_definition: &str,
+    value_set: &str,
+    value_types: &[codes::ObservationDataType],
+) -> Result<(String, Resource), Error> {
+    let mut element = vec![Some(
+        types::ElementDefinition::builder()
+            .id("Observation.code".to_owned())
+            .path("Observation.code".to_owned())
+            .binding(
+                types::ElementDefinitionBinding::builder()
+                    .strength(codes::BindingStrength::Required)
+                    .value_set(format!("{BASE_URL}/ValueSet/{value_set}"))
+                    .build()
+                    .context("ElementDefinitionBinding", None, None)?,
+            )
+            .build()
+            .context("ElementDefinition", None, None)?,
+    )];
+    if !value_types.is_empty() {
+        let mut r#type = Vec::new();
+        for value_type in value_types {
+            // The data type codes are the names of the FHIR types.
+            let code = serde_json::to_value(value_type)?;
+            r#type.push(Some(
+                types::ElementDefinitionType::builder()
+                    .code(code.as_str().unwrap_or_default().to_owned())
+                    .build()
+                    .context("ElementDefinitionType", None, None)?,
+            ));
+        }
+        element.push(Some(
+            types::ElementDefinition::builder()
+                .id("Observation.value[x]".to_owned())
+                .path("Observation.value[x]".to_owned())
+                .r#type(r#type)
+                .build()
+                .context("ElementDefinition", None, None)?,
+        ));
+    }
+
+    let url = format!("{BASE_URL}/StructureDefinition/{id}");
+    let profile = StructureDefinition::builder()
+        .id(id.to_owned())
+        .url(url.clone())
+        .version(VERSION.to_owned())
+        .name(name.to_owned())
+        .title(title.to_owned())
+        .status(codes::PublicationStatus::Active)
+        .description(description.to_owned())
+        .kind(codes::StructureDefinitionKind::Resource)
+        .r#abstract(false)
+        .r#type("Observation".to_owned())
+        .base_definition(base_definition.to_owned())
+        .derivation(codes::TypeDerivationRule::Constraint)
+        .differential(
+            StructureDefinitionDifferential::builder()
+                .element(element)
+                .build()
+                .context("StructureDefinitionDifferential", None, None)?,
+        )
+        .build()
+        .context("StructureDefinition", None, None)?;
+    Ok((url, Resource::StructureDefinition(profile)))
+}
+
+/// The canonical URL and resource of a ValueSet with the NPU codes, only those
+/// of `scale_type` if given.
+fn value_set(
+    name: &str,
+    title: &str,
+    description: &str,
+    scale_type: Option<&str>,
+) -> Result<(String, Resource), Error> {
+    let mut filter = Vec::new();
+    if let Some(scale_type) = scale_type {
+        filter.push(Some(
+            ValueSetComposeIncludeFilter::builder()
+                .property("scale_type".to_owned())
+                .op(codes::FilterOperator::Equal)
+                .value(scale_type.to_owned())
+                .build()
+                .context("ValueSetComposeIncludeFilter", None, None)?,
+        ));
+    }
+
+    let url = format!("{BASE_URL}/ValueSet/{name}");
+    let value_set = ValueSet::builder()
+        .id(name.to_owned())
+        .url(url.clone())
+        .version(VERSION.to_owned())
+        .name(name.to_owned())
+        .title(title.to_owned())
+        .status(codes::PublicationStatus::Active)
+        .description(description.to_owned())
+        .compose(
+            ValueSetCompose::builder()
+                .include(vec![Some(
+                    ValueSetComposeInclude::builder()
+                        .system(URL.to_owned())
+                        .version(VERSION.to_owned())
+                        .filter(filter)
+                        .build()
+                        .context("ValueSetComposeInclude", None, None)?,
+                )])
+                .build()
+                .context("ValueSetCompose", None, None)?,
+        )
+        .build()
+        .context("ValueSet", None, None)?;
+    Ok((url, Resource::ValueSet(value_set)))
+}
@@ -0,0 +1,264 @@
+//! RDF export of the current NPU concepts using SKOS, serialized as Turtle or
+//! N-Triples.
+//!
+//! Each NPU code is a `skos:Concept` in the `http://npu-terminology.org`
+//! concept scheme. The specialties are top concepts of the scheme and the NPU
+//! concepts are `skos:broader` to their specialty.
+
+use std::collections::BTreeSet;
+
+use crate::code_system::remove_duplicate_spaces;
+use crate::npu_concepts::{Npubegreber, split_codes};
+
+const SCHEME: &str = "http://npu-terminology.org";
+const CONCEPT_BASE: &str = "http://npu-terminology.org/concept/";
+const SPECIALTY_BASE: &str = "http://npu-terminology.org/specialty/";
+
+const PREFIXES: &[(&str, &str)] = &[
+    ("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#"),
+    ("skos", "http://www.w3.org/2004/02/skos/core#"),
+    ("dct", "http://purl.org/dc/terms/"),
+    ("owl", "http://www.w3.org/2002/07/owl#"),
+    ("xsd", "http://www.w3.org/2001/XMLSchema#"),
+    ("npu", CONCEPT_BASE),
+    ("specialty", SPECIALTY_BASE),
+];
+
+const LANGUAGE: &str = "en";
+
+enum Term {
+    Iri(String),
+    Literal(String),
+    LangLiteral(String),
+    Boolean(bool),
+}
+
+struct Triple {
+    subject: String,
+    predicate: &'static str,
+    object: Term,
+}
+
+/// Turtle, grouped by subject and using the prefixes in `PREFIXES`.
+pub fn turtle(data: &Npubegreber) -> String {
+    let mut out = String::new();
+    for (prefix, namespace) in PREFIXES {
+        out.push_str(&format!("@prefix {prefix}: <{namespace}> .\n"));
+    }
+
+    let mut previous_subject: Option<&str> = None;
+    let triples = triples(data);
+    for triple in &triples {
+        if previous_subject == Some(triple.subject.as_str()) {
+            out.push_str(" ;\n    ");
+        } else {
+            if previous_subject.is_some() {
+                out.push_str(" .\n");
+            }
+            out.push('\n');
+            out.push_str(&turtle_iri(&triple.subject));
+            out.push(' ');
+        }
+        out.push_str(&turtle_iri(triple.predicate));
+        out.push(' ');
+        out.push_str(&match &triple.object {
+            Term::Iri(iri) => turtle_iri(iri),
+            object => literal(object),
+        });
+        previous_subject = Some(&triple.subject);
+    }
+    if previous_subject.is_some() {
+        out.push_str(" .\n");
+    }
+    out
+}
+
+/// N-Triples, one triple per line with full IRIs.
+pub fn ntriples(data: &Npubegreber) -> String {
+    let mut out = String::new();
+    for triple in triples(data) {
+        out.push_str(&format!(
+            "<{}> {} {} .\n",
+            triple.subject,
+            ntriples_iri(triple.predicate),
+            match &triple.object {
+                Term::Iri(iri) => format!("<{iri}>"),
+                object => literal(object),
+            }
+        ));
+    }
+    out
+}
+
+fn triples(data: &Npubegreber) -> Vec<Triple> {
+    let mut triples = Vec::new();
+    let mut add = |subject: &str, predicate: &'static str, object: Term| {
+        triples.push(Triple {
+            subject: subject.to_owned(),
+            predicate,
+            object,
+        })
+    };
+
+    let specialties: BTreeSet<&str> = data
+        .current()
+        .map(|npubegreb| npubegreb.specialty.as_str())
+        .filter(|specialty| !specialty.is_empty())
+        .collect();
+
+    add(SCHEME, "rdf:type", Term::Iri(expand("skos:ConceptScheme")));
+    add(
+        SCHEME,
+        "skos:prefLabel",
+        Term::LangLiteral("NPU Terminology".to_owned()),
+    );
+    for specialty in &specialties {
+        add(
+            SCHEME,
+            "skos:hasTopConcept",
+            Term::Iri(specialty_iri(specialty)),
+        );
+    }
+
+    for specialty in &specialties {
+        let subject = specialty_iri(specialty);
+        add(&subject, "rdf:type", Term::Iri(expand("skos:Concept")));
+        add(&subject, "skos:inScheme", Term::Iri(SCHEME.to_owned()));
+        add(&subject, "skos:topConceptOf", Term::Iri(SCHEME.to_owned()));
+        add(
+            &subject,
+            "skos:notation",
+            Term::Literal(specialty.to_string()),
+        );
+        add(
+            &subject,
+            "skos:prefLabel",
+            Term::LangLiteral(specialty.to_string()),
+        );
+    }
+
+    for npubegreb in data.current() {
+        let subject = concept_iri(&npubegreb.npu_code);
+        add(&subject, "rdf:type", Term::Iri(expand("skos:Concept")));
+        add(&subject, "skos:inScheme", Term::Iri(SCHEME.to_owned()));
+        add(
+            &subject,
+            "skos:notation",
+            Term::Literal(npubegreb.npu_code.clone()),
+        );
+        let pref_label = remove_duplicate_spaces(&npubegreb.short_definition);
+        if !pref_label.is_empty() {
+            add(&subject, "skos:prefLabel", Term::LangLiteral(pref_label));
+        }
+        let definition = remove_duplicate_spaces(&npubegreb.full_definition);
+        if !definition.is_empty() {
+            add(&subject, "skos:definition", Term::LangLiteral(definition));
+        }
+        if !npubegreb.specialty.is_empty() {
+            add(
+                &subject,
+                "skos:broader",
+                Term::Iri(specialty_iri(&npubegreb.specialty)),
+            );
+        }
+        for replaced in split_codes(&npubegreb.replaces) {
+            add(&subject, "dct:replaces", Term::Iri(concept_iri(replaced)));
+        }
+        for replacement in split_codes(&npubegreb.replaced_by) {
+            add(
+                &subject,
+                "dct:isReplacedBy",
+                Term::Iri(concept_iri(replacement)),
+            );
+        }
+        if npubegreb.active != "1" {
+            add(&subject, "owl:deprecated", Term::Boolean(true));
+        }
+    }
+
+    triples
+}
+
+fn concept_iri(code: &str) -> String {
+    format!("{CONCEPT_BASE}{}", percent_encode(code))
+}
+
+fn specialty_iri(specialty: &str) -> String {
+    format!("{SPECIALTY_BASE}{}", percent_encode(specialty))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Expand a `prefix:name` to the full IRI.
+fn expand(name: &str) -> String {
+    let (prefix, local) = name.split_once(':').expect("prefixed name");
+    let namespace = PREFIXES
+        .iter()
+        .find(|(p, _)| *p == prefix)
+        .map(|(_, namespace)| *namespace)
+        .expect("known prefix");
+    format!("{namespace}{local}")
+}
+
+fn ntriples_iri(name: &str) -> String {
+    format!("<{}>", expand(name))
+}
+
+/// A prefixed name if the IRI is in one of the `PREFIXES` namespaces and the
+/// local part needs no escaping, otherwise the full IRI. Names that are
+/// already prefixed are returned as is.
+fn turtle_iri(iri: &str) -> String {
+    if iri == "rdf:type" {
+        return "a".to_owned();
+    }
+    if !iri.contains("://") {
+        return iri.to_owned();
+    }
+    for (prefix, namespace) in PREFIXES {
+        if let Some(local) = iri.strip_prefix(namespace)
+            && !local.is_empty()
+            && local
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return format!("{prefix}:{local}");
+        }
+    }
+    format!("<{iri}>")
+}
+
+fn literal(term: &Term) -> String {
+    match term {
+        Term::Literal(value) => quote(value),
+        Term::LangLiteral(value) => format!("{}@{LANGUAGE}", quote(value)),
+        Term::Boolean(value) => format!("\"{value}\"^^<{}>", expand("xsd:boolean")),
+        Term::Iri(iri) => format!("<{iri}>"),
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,378 @@
+//! Record-level reading of the NPU XML.
+//!
+//! The document is decoded to UTF-8 from the encoding of its byte order mark or
+//! XML declaration, as the XML parser would. The `<Npubegreb>` elements are
+//! then located in the bytes and deserialized one at a time, so a malformed
+//! record is skipped and reported instead of failing the whole file.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreb, Npubegreber};
+
+const ROOT: &[u8] = b"<Npubegreber";
+const START: &[u8] = b"<Npubegreb";
+const END: &[u8] = b"</Npubegreb>";
+
+/// A record that was skipped.
+pub struct RecordError {
+    /// Byte offset of the record's start tag in the document decoded to UTF-8.
+    pub offset: usize,
+    /// 1-based line of the record's start tag.
+    pub line: usize,
+    /// The record's `npu_code`, if it could be found.
+    pub npu_code: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} (byte {})", self.line, self.offset)?;
+        if let Some(npu_code) = &self.npu_code {
+            write!(f, " {npu_code}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Read the NPU XML file at `path`, skipping malformed records. Fails if more
+/// than `max_errors` records are malformed.
+pub fn read(
+    path: &str,
+    max_errors: Option<usize>,
+) -> Result<(Npubegreber, Vec<RecordError>), Error> {
+    let file = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+
+    // Without a supported encoding or a root element there is nothing to
+    // recover; let the parser report what is wrong with the file.
+    let Some(bytes) = decode(&file).filter(|bytes| find(bytes, ROOT, 0).is_some()) else {
+        return serde_xml_rs::from_reader::<Npubegreber, _>(file.as_slice())
+            .map(|data| (data, Vec::new()))
+            .map_err(|source| Error::XmlParse {
+                path: path.to_owned(),
+                source,
+            });
+    };
+
+    let mut npubegreb = Vec::new();
+    let mut errors = Vec::new();
+    let mut position = 0;
+    // The line of `counted`, advanced with the records so that the lines are
+    // counted once.
+    let (mut line, mut counted) = (1, 0);
+    while let Some(start) = find_start(&bytes, position) {
+        // A record ends at its end tag, or at the next start tag if the end
+        // tag is missing.
+        let next_start = find_start(&bytes, start + START.len());
+        let (end, closed) = match find(&bytes, END, start) {
+            Some(end) if next_start.is_none_or(|next| end < next) => (end + END.len(), true),
+            _ => (next_start.unwrap_or(bytes.len()), false),
+        };
+        let record = &bytes[start..end];
+        position = end;
+
+        let result = if closed {
+            parse_record(record)
+        } else {
+            Err("missing </Npubegreb> end tag".to_owned())
+        };
+        match result {
+            Ok(record) => npubegreb.push(record),
+            Err(message) => {
+                line += bytes[counted..start]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                counted = start;
+                errors.push(RecordError {
+                    offset: start,
+                    line,
+                    npu_code: npu_code(record),
+                    message,
+                });
+                if let Some(max_errors) = max_errors
+                    && errors.len() > max_errors
+                {
+                    return Err(Error::MalformedRecords {
+                        path: path.to_owned(),
+                        count: errors.len(),
+                        max_errors,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok((
+        Npubegreber {
+            text: None,
+            npubegreb,
+        },
+        errors,
+    ))
+}
+
+/// `file` decoded to UTF-8 from the encoding of its byte order mark or XML
+/// declaration, UTF-8 if it has neither. Invalid UTF-8 is kept, to be reported
+/// per record. `None` if the encoding is not one the XML parser supports.
+fn decode(file: &[u8]) -> Option<Cow<'_, [u8]>> {
+    match file {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => return Some(Cow::Borrowed(rest)),
+        [0xFF, 0xFE, rest @ ..] => return Some(Cow::Owned(decode_utf16(rest, u16::from_le_bytes))),
+        [0xFE, 0xFF, rest @ ..] => return Some(Cow::Owned(decode_utf16(rest, u16::from_be_bytes))),
+        _ => {}
+    }
+    let Some(encoding) = declared_encoding(file) else {
+        return Some(Cow::Borrowed(file));
+    };
+    match encoding.to_ascii_lowercase().as_str() {
+        // ASCII is a subset of UTF-8.
+        "utf-8" | "utf8" | "ascii" | "us-ascii" => Some(Cow::Borrowed(file)),
+        "iso-8859-1" | "latin1" => Some(Cow::Owned(
+            file.iter()
+                .map(|&b| char::from(b))
+                .collect::<String>()
+                .into_bytes(),
+        )),
+        // Without a byte order mark, the byte order is that of the `<` of the
+        // declaration.
+        "utf-16" | "utf16" => match file {
+            [0x3C, 0x00, ..] => Some(Cow::Owned(decode_utf16(file, u16::from_le_bytes))),
+            [0x00, 0x3C, ..] => Some(Cow::Owned(decode_utf16(file, u16::from_be_bytes))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The `encoding` of the XML declaration at the start of `file`, if any.
+fn declared_encoding(file: &[u8]) -> Option<&str> {
+    if !file.starts_with(b"<?xml") {
+        return None;
+    }
+    let declaration = &file[..find(file, b"?>", 0)?];
+    let value = &declaration[find(declaration, b"encoding", 0)? + b"encoding".len()..];
+    let value = value
+        .trim_ascii_start()
+        .strip_prefix(b"=")?
+        .trim_ascii_start();
+    let (&quote, value) = value.split_first()?;
+    let end = value.iter().position(|&b| b == quote)?;
+    std::str::from_utf8(&value[..end]).ok()
+}
+
+/// UTF-16 code units from pairs of bytes with `from_bytes`, as UTF-8. Unpaired
+/// surrogates become U+FFFD.
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Vec<u8> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn parse_record(record: &[u8]) -> Result<Npubegreb, String> {
+    let text = std::str::from_utf8(record)
+        .map_err(|e| format!("invalid UTF-8 at byte {} of the record", e.valid_up_to()))?;
+    serde_xml_rs::from_str(text).map_err(|e| e.to_string())
+}
+
+/// The text of the `npu_code` element, decoded lossily so that it is also
+/// found in records with invalid UTF-8.
+fn npu_code(record: &[u8]) -> Option<String> {
+    let start = find(record, b"<npu_code>", 0)? + b"<npu_code>".len();
+    let end = find(record, b"</npu_code>", start)?;
+    let npu_code = String::from_utf8_lossy(&record[start..end])
+        .trim()
+        .to_owned();
+    (!npu_code.is_empty()).then_some(npu_code)
+}
+
+/// The next `<Npubegreb>` start tag at or after `from`, excluding the
+/// `<Npubegreber>` root.
+fn find_start(bytes: &[u8], from: usize) -> Option<usize> {
+    let mut from = from;
+    while let Some(start) = find(bytes, START, from) {
+        match bytes.get(start + START.len()) {
+            Some(b'>' | b'/' | b' ' | b'\t' | b'\r' | b'\n') => return Some(start),
+            _ => from = start + START.len(),
+        }
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DATA: &str = include_str!("../testdata/npu_test_data.xml");
+
+    fn read_bytes(
+        name: &str,
+        bytes: &[u8],
+        max_errors: Option<usize>,
+    ) -> Result<(Npubegreber, Vec<RecordError>), Error> {
+        let path =
+            std::env::temp_dir().join(format!("npu-reader-{}-{name}.xml", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let result = read(path.to_str().unwrap(), max_errors);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn rows(data: &Npubegreber) -> serde_json::Value {
+        serde_json::to_value(&data.npubegreb).unwrap()
+    }
+
+    /// The line of the `<Npubegreb>` start tag of the record containing `s`.
+    fn record_line(s: &str) -> usize {
+        let start = TEST_DATA[..TEST_DATA.find(s).unwrap()]
+            .rfind("<Npubegreb>")
+            .unwrap();
+        TEST_DATA[..start].matches('\n').count() + 1
+    }
+
+    #[test]
+    fn test_data() {
+        let (data, errors) = read_bytes("utf-8", TEST_DATA.as_bytes(), None).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(data.npubegreb.len(), 21);
+        assert_eq!(
+            rows(&data),
+            rows(&serde_xml_rs::from_str::<Npubegreber>(TEST_DATA).unwrap())
+        );
+    }
+
+    #[test]
+    fn declared_latin1() {
+        // The em dash is not in ISO-8859-1, so it is written as a reference.
+        let text = TEST_DATA
+            .replace("encoding=\"UTF-8\"", "encoding=\"ISO-8859-1\"")
+            .replace('—', "&#8212;");
+        let bytes: Vec<u8> = text
+            .chars()
+            .map(|c| u8::try_from(c).expect("only ISO-8859-1 characters"))
+            .collect();
+        let (data, errors) = read_bytes("latin1", &bytes, None).unwrap();
+        assert!(errors.is_empty());
+        let (expected, _) = read_bytes("latin1-utf-8", TEST_DATA.as_bytes(), None).unwrap();
+        assert_eq!(rows(&data), rows(&expected));
+    }
+
+    #[test]
+    fn utf16_with_byte_order_mark() {
+        let text = TEST_DATA.replace("encoding=\"UTF-8\"", "encoding=\"UTF-16\"");
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        let (data, errors) = read_bytes("utf-16", &bytes, None).unwrap();
+        assert!(errors.is_empty());
+        let (expected, _) = read_bytes("utf-16-utf-8", TEST_DATA.as_bytes(), None).unwrap();
+        assert_eq!(rows(&data), rows(&expected));
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        let text = TEST_DATA.replace("encoding=\"UTF-8\"", "encoding=\"KOI8-R\"");
+        let result = read_bytes("koi8-r", text.as_bytes(), None);
+        assert!(matches!(result, Err(Error::XmlParse { .. })));
+    }
+
+    #[test]
+    fn invalid_utf8_skips_the_record() {
+        let mut bytes = Vec::new();
+        for (i, part) in TEST_DATA.split("NPU01003</npu_code>").enumerate() {
+            if i > 0 {
+                bytes.extend(b"NPU01003</npu_code>\xFF");
+            }
+            bytes.extend(part.as_bytes());
+        }
+        let (data, errors) = read_bytes("invalid-utf-8", &bytes, None).unwrap();
+        assert_eq!(data.npubegreb.len(), 20);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].npu_code.as_deref(), Some("NPU01003"));
+        assert_eq!(errors[0].line, record_line("NPU01003</npu_code>"));
+        assert!(errors[0].message.contains("invalid UTF-8"));
+    }
+
+    #[test]
+    fn missing_end_tag() {
+        let start = TEST_DATA.find("NPU01005</npu_code>").unwrap();
+        let end = start + TEST_DATA[start..].find("</Npubegreb>").unwrap();
+        let text = format!(
+            "{}{}",
+            &TEST_DATA[..end],
+            &TEST_DATA[end + "</Npubegreb>".len()..]
+        );
+        let (data, errors) = read_bytes("missing-end-tag", text.as_bytes(), None).unwrap();
+        assert_eq!(data.npubegreb.len(), 20);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].npu_code.as_deref(), Some("NPU01005"));
+        assert_eq!(errors[0].line, record_line("NPU01005</npu_code>"));
+        assert_eq!(errors[0].message, "missing </Npubegreb> end tag");
+        assert!(
+            data.npubegreb
+                .iter()
+                .any(|npubegreb| npubegreb.npu_code == "NPU01006")
+        );
+    }
+
+    #[test]
+    fn lines_of_several_errors() {
+        let text = TEST_DATA
+            .replace("<npu_code>NPU01004", "<npu_code><NPU01004")
+            .replace("<npu_code>NPU01009", "<npu_code><NPU01009");
+        let (_, errors) = read_bytes("lines", text.as_bytes(), None).unwrap();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(
+            lines,
+            [
+                record_line("NPU01004</npu_code>"),
+                record_line("NPU01009</npu_code>")
+            ]
+        );
+    }
+
+    #[test]
+    fn max_errors() {
+        let text = TEST_DATA
+            .replace("<npu_code>NPU01004", "<npu_code><NPU01004")
+            .replace("<npu_code>NPU01009", "<npu_code><NPU01009");
+        assert!(matches!(
+            read_bytes("max-errors-1", text.as_bytes(), Some(1)),
+            Err(Error::MalformedRecords {
+                count: 2,
+                max_errors: 1,
+                ..
+            })
+        ));
+        let (data, errors) = read_bytes("max-errors-2", text.as_bytes(), Some(2)).unwrap();
+        assert_eq!((data.npubegreb.len(), errors.len()), (19, 2));
+    }
+
+    #[test]
+    fn no_root_element() {
+        let result = read_bytes("no-root", b"<?xml version=\"1.0\"?>\n<other/>\n", None);
+        assert!(matches!(result, Err(Error::XmlParse { .. })));
+    }
+
+    #[test]
+    fn declaration_encoding() {
+        assert_eq!(
+            declared_encoding(b"<?xml version='1.0' encoding = 'latin1'?><a/>"),
+            Some("latin1")
+        );
+        assert_eq!(declared_encoding(b"<?xml version=\"1.0\"?><a/>"), None);
+        assert_eq!(declared_encoding(b"<a encoding=\"latin1\"/>"), None);
+    }
+}
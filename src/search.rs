@@ -0,0 +1,436 @@
+//! Full-text search over the current versions of the NPU concepts, with
+//! facets on specialty, system, unit and scale type.
+//!
+//! The tokenizer splits NPU notation such as `P—Sodium ion; subst.c.(proc.)`
+//! at the em dash, semicolons, parentheses and other punctuation, and between
+//! letters and digits, and spells out Greek letters so that `β2` and
+//! `beta-2` both give the tokens `beta` and `2`. Tokens are lowercased and
+//! Latin letters lose their accents, so `Protéine` matches `protein`. Query
+//! terms of three or more characters also match as prefixes, at a lower
+//! weight.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::code_system::remove_duplicate_spaces;
+use crate::npu_concepts::Npubegreber;
+
+/// The weight of a match in each indexed field.
+const COMPONENT_WEIGHT: f64 = 3.0;
+const SHORT_DEFINITION_WEIGHT: f64 = 2.0;
+const SYNONYM_WEIGHT: f64 = 2.0;
+const FULL_DEFINITION_WEIGHT: f64 = 1.0;
+
+/// The weight of a prefix match relative to a whole-token match.
+const PREFIX_WEIGHT: f64 = 0.5;
+/// Query terms shorter than this only match whole tokens.
+const MIN_PREFIX_LEN: usize = 3;
+/// Factor for the score of retired concepts, which rank below active ones.
+const RETIRED_WEIGHT: f64 = 0.5;
+/// Score of a query that is the code itself.
+const CODE_SCORE: f64 = 1000.0;
+
+/// The NPU fields that results can be filtered and counted by.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Facet {
+    Specialty,
+    System,
+    Unit,
+    ScaleType,
+}
+
+impl Facet {
+    const ALL: [Facet; 4] = [
+        Facet::Specialty,
+        Facet::System,
+        Facet::Unit,
+        Facet::ScaleType,
+    ];
+}
+
+impl fmt::Display for Facet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Facet::Specialty => write!(f, "specialty"),
+            Facet::System => write!(f, "system"),
+            Facet::Unit => write!(f, "unit"),
+            Facet::ScaleType => write!(f, "scale_type"),
+        }
+    }
+}
+
+struct Document {
+    code: String,
+    display: String,
+    active: bool,
+    /// The values of the facets, in the order of `Facet::ALL`.
+    facets: [String; 4],
+}
+
+impl Document {
+    fn facet(&self, facet: Facet) -> &str {
+        &self.facets[facet as usize]
+    }
+}
+
+pub struct Index {
+    documents: Vec<Document>,
+    /// The documents containing each token, with the weight of the best field
+    /// the token occurs in.
+    postings: BTreeMap<String, Vec<(usize, f64)>>,
+}
+
+#[derive(Serialize)]
+pub struct Hit {
+    pub code: String,
+    pub display: String,
+    pub active: bool,
+    pub score: f64,
+}
+
+#[derive(Serialize)]
+pub struct Results {
+    /// Number of matching concepts, of which at most the limit are in `hits`.
+    pub total: usize,
+    pub hits: Vec<Hit>,
+    /// Number of matching concepts per value of each facet.
+    pub facets: BTreeMap<Facet, BTreeMap<String, usize>>,
+}
+
+impl Index {
+    /// Index the current versions of the concepts in `data`.
+    pub fn new(data: &Npubegreber) -> Self {
+        let mut documents = Vec::new();
+        let mut postings = BTreeMap::<String, Vec<(usize, f64)>>::new();
+        for npubegreb in data.current() {
+            let id = documents.len();
+            let mut weights = HashMap::<String, f64>::new();
+            for (text, weight) in [
+                (npubegreb.component.as_str(), COMPONENT_WEIGHT),
+                (npubegreb.short_definition.as_str(), SHORT_DEFINITION_WEIGHT),
+                (npubegreb.full_definition.as_str(), FULL_DEFINITION_WEIGHT),
+                // The abbreviations of the axes, e.g. P for Plasma.
+                (npubegreb.system_short.as_str(), SYNONYM_WEIGHT),
+                (npubegreb.component_short.as_str(), SYNONYM_WEIGHT),
+                (npubegreb.kind_of_property_short.as_str(), SYNONYM_WEIGHT),
+                (npubegreb.unit_short.as_str(), SYNONYM_WEIGHT),
+            ] {
+                for token in tokenize(text) {
+                    let best = weights.entry(token).or_default();
+                    *best = best.max(weight);
+                }
+            }
+            for (token, weight) in weights {
+                postings.entry(token).or_default().push((id, weight));
+            }
+
+            documents.push(Document {
+                code: npubegreb.npu_code.clone(),
+                display: remove_duplicate_spaces(&npubegreb.short_definition),
+                active: npubegreb.active == "1",
+                facets: [
+                    npubegreb.specialty.trim().to_owned(),
+                    npubegreb.system.trim().to_owned(),
+                    npubegreb.unit.trim().to_owned(),
+                    npubegreb.scale_type.trim().to_owned(),
+                ],
+            });
+        }
+        Index {
+            documents,
+            postings,
+        }
+    }
+
+    /// The concepts matching all terms of `query` and the facet `filters`,
+    /// best first, at most `limit`.
+    pub fn search(&self, query: &str, filters: &[(Facet, String)], limit: usize) -> Results {
+        let mut scores = HashMap::<usize, f64>::new();
+        let terms = tokenize(query);
+        for (i, term) in terms.iter().enumerate() {
+            let mut term_scores = HashMap::<usize, f64>::new();
+            for (token, postings) in self.postings.range(term.clone()..) {
+                let factor = if token == term {
+                    1.0
+                } else if term.chars().count() >= MIN_PREFIX_LEN && token.starts_with(term) {
+                    PREFIX_WEIGHT
+                } else {
+                    break;
+                };
+                let idf = self.idf(postings.len());
+                for &(id, weight) in postings {
+                    let score = term_scores.entry(id).or_default();
+                    *score = score.max(factor * weight * idf);
+                }
+            }
+            // Every term has to match.
+            if i == 0 {
+                scores = term_scores;
+            } else {
+                scores.retain(|id, score| match term_scores.get(id) {
+                    Some(term_score) => {
+                        *score += term_score;
+                        true
+                    }
+                    None => false,
+                });
+            }
+        }
+
+        let code = query.trim();
+        if let Some(id) = self
+            .documents
+            .iter()
+            .position(|document| document.code.eq_ignore_ascii_case(code))
+        {
+            scores.insert(id, CODE_SCORE);
+        }
+
+        let mut matches: Vec<(usize, f64)> = scores
+            .into_iter()
+            .filter(|(id, _)| {
+                filters.iter().all(|(facet, value)| {
+                    self.documents[*id]
+                        .facet(*facet)
+                        .eq_ignore_ascii_case(value.trim())
+                })
+            })
+            .map(|(id, score)| {
+                if self.documents[id].active {
+                    (id, score)
+                } else {
+                    (id, score * RETIRED_WEIGHT)
+                }
+            })
+            .collect();
+        matches.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| self.documents[*a].code.cmp(&self.documents[*b].code))
+        });
+
+        let mut facets = BTreeMap::<Facet, BTreeMap<String, usize>>::new();
+        for (id, _) in &matches {
+            for facet in Facet::ALL {
+                let value = self.documents[*id].facet(facet);
+                if !value.is_empty() {
+                    *facets
+                        .entry(facet)
+                        .or_default()
+                        .entry(value.to_owned())
+                        .or_default() += 1;
+                }
+            }
+        }
+
+        Results {
+            total: matches.len(),
+            hits: matches
+                .into_iter()
+                .take(limit)
+                .map(|(id, score)| {
+                    let document = &self.documents[id];
+                    Hit {
+                        code: document.code.clone(),
+                        display: document.display.clone(),
+                        active: document.active,
+                        score: (score * 100.0).round() / 100.0,
+                    }
+                })
+                .collect(),
+            facets,
+        }
+    }
+
+    /// Inverse document frequency of a token in `documents` concepts.
+    fn idf(&self, documents: usize) -> f64 {
+        let n = self.documents.len() as f64;
+        let df = documents as f64;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+}
+
+/// Lowercase tokens of `text` without accents: runs of letters or of digits,
+/// with each Greek letter spelled out as its own token.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut current_is_digit = false;
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if let Some(name) = greek_name(c) {
+            push_token(&mut tokens, &mut current);
+            tokens.push(name.to_owned());
+        } else if c.is_alphanumeric() {
+            let is_digit = c.is_numeric();
+            if is_digit != current_is_digit {
+                push_token(&mut tokens, &mut current);
+            }
+            current_is_digit = is_digit;
+            match fold_accent(c) {
+                Some(folded) => current.push_str(folded),
+                None => current.push(c),
+            }
+        } else {
+            push_token(&mut tokens, &mut current);
+        }
+    }
+    push_token(&mut tokens, &mut current);
+    tokens
+}
+
+fn push_token(tokens: &mut Vec<String>, current: &mut String) {
+    if !current.is_empty() {
+        tokens.push(std::mem::take(current));
+    }
+}
+
+fn greek_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        'α' => "alpha",
+        'β' => "beta",
+        'γ' => "gamma",
+        'δ' => "delta",
+        'ε' => "epsilon",
+        'ζ' => "zeta",
+        'η' => "eta",
+        'θ' => "theta",
+        'ι' => "iota",
+        'κ' => "kappa",
+        'λ' => "lambda",
+        'ν' => "nu",
+        'ξ' => "xi",
+        'ο' => "omicron",
+        'π' => "pi",
+        'ρ' => "rho",
+        'σ' | 'ς' => "sigma",
+        'τ' => "tau",
+        'υ' => "upsilon",
+        'φ' => "phi",
+        'χ' => "chi",
+        'ψ' => "psi",
+        'ω' => "omega",
+        // Units such as µmol/L are written with both the micro sign and the
+        // Greek letter mu.
+        'µ' | 'μ' => "micro",
+        _ => return None,
+    })
+}
+
+/// The unaccented spelling of a lowercase Latin letter with a diacritic.
+fn fold_accent(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' => "i",
+        'ł' | 'ľ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ß' => "ss",
+        'ť' | 'ţ' => "t",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    fn codes(results: &Results) -> Vec<&str> {
+        results.hits.iter().map(|hit| hit.code.as_str()).collect()
+    }
+
+    #[test]
+    fn tokenize_splits_npu_notation() {
+        assert_eq!(
+            tokenize("P—Sodium ion; subst.c.(proc.) = ? mmol/L"),
+            ["p", "sodium", "ion", "subst", "c", "proc", "mmol", "l"]
+        );
+    }
+
+    #[test]
+    fn tokenize_folds_case_and_accents() {
+        assert_eq!(tokenize("PROTÉINE Ærø"), tokenize("protéine ærø"));
+        assert_eq!(tokenize("Protéine Ærø"), ["proteine", "aero"]);
+    }
+
+    #[test]
+    fn tokenize_spells_out_greek_letters() {
+        assert_eq!(tokenize("β2-Microglobulin"), ["beta", "2", "microglobulin"]);
+        assert_eq!(tokenize("beta-2"), ["beta", "2"]);
+        assert_eq!(tokenize("Β2"), tokenize("β2"));
+    }
+
+    #[test]
+    fn micro_sign_and_mu_give_the_same_tokens() {
+        assert_eq!(tokenize("µmol/L"), ["micro", "mol", "l"]);
+        assert_eq!(tokenize("μmol/L"), tokenize("µmol/L"));
+    }
+
+    #[test]
+    fn search_with_mu_finds_micro_sign_units() {
+        let index = Index::new(&test_data());
+        let micro = index.search("µmol", &[], 20);
+        let mu = index.search("μmol", &[], 20);
+        assert_eq!(micro.total, 5);
+        assert_eq!(codes(&mu), codes(&micro));
+    }
+
+    #[test]
+    fn results_are_ordered_by_score_then_code() {
+        let index = Index::new(&test_data());
+        let results = index.search("acet", &[], 20);
+        assert_eq!(results.total, 8);
+        // Whole-word matches of the component rank above prefix matches,
+        // ties are ordered by code and retired concepts come last.
+        assert_eq!(
+            codes(&results)[..6],
+            [
+                "NPU01005", "NPU01006", "NPU01009", "NPU01010", "NPU01011", "NPU01007"
+            ]
+        );
+        assert!(
+            results
+                .hits
+                .windows(2)
+                .all(|pair| pair[0].score >= pair[1].score)
+        );
+        assert!(!results.hits[5].active);
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let index = Index::new(&test_data());
+        let results = index.search("urine acetaldehyde", &[], 20);
+        assert_eq!(codes(&results), ["NPU01006"]);
+    }
+
+    #[test]
+    fn code_query_ranks_first() {
+        let index = Index::new(&test_data());
+        let results = index.search("npu01006", &[], 20);
+        assert_eq!(codes(&results), ["NPU01006"]);
+        assert_eq!(results.hits[0].score, CODE_SCORE);
+    }
+
+    #[test]
+    fn facet_filters_and_counts() {
+        let index = Index::new(&test_data());
+        let filters = [(Facet::System, "urine".to_owned())];
+        let results = index.search("acetaldehyde", &filters, 20);
+        assert_eq!(codes(&results), ["NPU01006"]);
+        assert_eq!(results.facets[&Facet::System]["Urine"], 1);
+    }
+}
@@ -0,0 +1,425 @@
+//! Static HTML browser for an NPU release, usable offline from the file
+//! system.
+//!
+//! The site has an index by specialty and system, a page per code with its
+//! properties, version history and replacements, and a search index that is
+//! loaded as a script so that searching works without a web server.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::code_system::remove_duplicate_spaces;
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreb, Npubegreber, split_codes};
+use crate::xml::escape;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; padding: 0 1em; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+.retired { color: #888; }
+#search { font-size: 1.1em; width: 100%; }
+#results li { margin: 0.2em 0; }
+";
+
+const SEARCH: &str = "\
+var input = document.getElementById('search');
+var results = document.getElementById('results');
+input.addEventListener('input', function () {
+    var terms = input.value.toLowerCase().split(/\\s+/).filter(function (t) { return t; });
+    results.innerHTML = '';
+    if (!terms.length) {
+        return;
+    }
+    var matches = NPU_SEARCH_INDEX.filter(function (entry) {
+        var text = (entry.code + ' ' + entry.text).toLowerCase();
+        return terms.every(function (term) { return text.indexOf(term) >= 0; });
+    });
+    matches.slice(0, 100).forEach(function (entry) {
+        var item = document.createElement('li');
+        var link = document.createElement('a');
+        link.href = 'code/' + entry.page;
+        link.textContent = entry.code;
+        item.appendChild(link);
+        item.appendChild(document.createTextNode(' ' + entry.display));
+        if (!entry.active) {
+            item.className = 'retired';
+        }
+        results.appendChild(item);
+    });
+    if (matches.length > 100) {
+        var more = document.createElement('li');
+        more.textContent = (matches.length - 100) + ' more';
+        results.appendChild(more);
+    }
+});
+";
+
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+    code: &'a str,
+    page: String,
+    display: String,
+    /// The definitions and axes, matched by the search terms.
+    text: String,
+    active: bool,
+}
+
+/// Write the site to the directory `dir`, which is created if needed.
+pub fn write(dir: &Path, data: &Npubegreber) -> Result<(), Error> {
+    // All rows per code, in input order, and the row that is shown for the
+    // code: its current version, or its last row if it has none.
+    let mut versions = BTreeMap::<&str, Vec<&Npubegreb>>::new();
+    for npubegreb in &data.npubegreb {
+        versions
+            .entry(npubegreb.npu_code.as_str())
+            .or_default()
+            .push(npubegreb);
+    }
+    let shown: BTreeMap<&str, &Npubegreb> = versions
+        .iter()
+        .map(|(code, rows)| {
+            let row = rows
+                .iter()
+                .find(|npubegreb| npubegreb.is_current())
+                .unwrap_or_else(|| rows.last().expect("codes have at least one row"));
+            (*code, *row)
+        })
+        .collect();
+    // The codes whose shown row names each code as its replacement.
+    let mut replaced = BTreeMap::<&str, Vec<&str>>::new();
+    for (code, npubegreb) in &shown {
+        for replacement in split_codes(&npubegreb.replaced_by) {
+            let codes = replaced.entry(replacement).or_default();
+            if codes.last() != Some(code) {
+                codes.push(code);
+            }
+        }
+    }
+
+    for subdirectory in ["code", "specialty", "system"] {
+        let path = dir.join(subdirectory);
+        fs::create_dir_all(&path).map_err(|e| Error::io(path.display().to_string(), e))?;
+    }
+
+    let mut by_specialty = BTreeMap::<&str, Vec<&Npubegreb>>::new();
+    let mut by_system = BTreeMap::<&str, Vec<&Npubegreb>>::new();
+    for npubegreb in shown.values() {
+        by_specialty
+            .entry(npubegreb.specialty.trim())
+            .or_default()
+            .push(npubegreb);
+        by_system
+            .entry(npubegreb.system.trim())
+            .or_default()
+            .push(npubegreb);
+    }
+
+    write_file(dir, "style.css", STYLE)?;
+    write_file(dir, "search.js", SEARCH)?;
+    write_file(dir, "search-index.js", &search_index(&shown)?)?;
+    write_file(dir, "index.html", &index(&shown, &by_specialty, &by_system))?;
+    for (kind, groups) in [("specialty", &by_specialty), ("system", &by_system)] {
+        for (name, rows) in groups {
+            write_file(
+                dir,
+                &format!("{kind}/{}", group_page(name)),
+                &group(kind, name, rows),
+            )?;
+        }
+    }
+    for (code, npubegreb) in &shown {
+        write_file(
+            dir,
+            &format!("code/{}", code_page(code)),
+            &concept(
+                npubegreb,
+                &versions[code],
+                replaced.get(code).map_or(&[], Vec::as_slice),
+                &shown,
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_file(dir: &Path, name: &str, contents: &str) -> Result<(), Error> {
+    let path = dir.join(name);
+    fs::write(&path, contents).map_err(|e| Error::io(path.display().to_string(), e))
+}
+
+fn search_index(shown: &BTreeMap<&str, &Npubegreb>) -> Result<String, Error> {
+    let entries: Vec<SearchEntry> = shown
+        .iter()
+        .map(|(code, npubegreb)| SearchEntry {
+            code,
+            page: code_page(code),
+            display: remove_duplicate_spaces(&npubegreb.short_definition),
+            text: remove_duplicate_spaces(&format!(
+                "{} {} {} {} {} {} {}",
+                npubegreb.short_definition,
+                npubegreb.full_definition,
+                npubegreb.system,
+                npubegreb.component,
+                npubegreb.kind_of_property,
+                npubegreb.unit,
+                npubegreb.specialty
+            )),
+            active: npubegreb.active == "1",
+        })
+        .collect();
+    Ok(format!(
+        "var NPU_SEARCH_INDEX = {};\n",
+        serde_json::to_string(&entries)?
+    ))
+}
+
+fn index(
+    shown: &BTreeMap<&str, &Npubegreb>,
+    by_specialty: &BTreeMap<&str, Vec<&Npubegreb>>,
+    by_system: &BTreeMap<&str, Vec<&Npubegreb>>,
+) -> String {
+    let active = shown
+        .values()
+        .filter(|npubegreb| npubegreb.active == "1")
+        .count();
+    let mut body = format!(
+        "<h1>NPU Terminology</h1>
+<p>{} codes, {active} active and {} retired.</p>
+<p><input id=\"search\" type=\"search\" placeholder=\"Search codes and definitions\" autofocus></p>
+<ul id=\"results\"></ul>
+",
+        shown.len(),
+        shown.len() - active
+    );
+    for (kind, heading, groups) in [
+        ("specialty", "Specialties", by_specialty),
+        ("system", "Systems", by_system),
+    ] {
+        body.push_str(&format!("<h2>{heading}</h2>\n<ul>\n"));
+        for (name, rows) in groups {
+            body.push_str(&format!(
+                "<li><a href=\"{kind}/{}\">{}</a> ({})</li>\n",
+                group_page(name),
+                escape(group_name(name)),
+                rows.len()
+            ));
+        }
+        body.push_str("</ul>\n");
+    }
+    body.push_str(
+        "<script src=\"search-index.js\"></script>\n<script src=\"search.js\"></script>\n",
+    );
+    page("NPU Terminology", "", &body)
+}
+
+fn group(kind: &str, name: &str, rows: &[&Npubegreb]) -> String {
+    let title = format!("{} {}", capitalize(kind), group_name(name));
+    let mut body = format!(
+        "<p><a href=\"../index.html\">NPU Terminology</a></p>\n<h1>{}</h1>\n<table>\n<tr><th>Code</th><th>Short definition</th><th>Status</th></tr>\n",
+        escape(&title)
+    );
+    for npubegreb in rows {
+        body.push_str(&format!(
+            "<tr{}><td><a href=\"../code/{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            class(npubegreb),
+            code_page(&npubegreb.npu_code),
+            escape(&npubegreb.npu_code),
+            escape(&remove_duplicate_spaces(&npubegreb.short_definition)),
+            status(npubegreb)
+        ));
+    }
+    body.push_str("</table>\n");
+    page(&title, "../", &body)
+}
+
+fn concept(
+    npubegreb: &Npubegreb,
+    versions: &[&Npubegreb],
+    replaced: &[&str],
+    shown: &BTreeMap<&str, &Npubegreb>,
+) -> String {
+    let code = &npubegreb.npu_code;
+    let mut body = format!(
+        "<p><a href=\"../index.html\">NPU Terminology</a></p>
+<h1>{}</h1>
+<p>{}</p>
+<p>{}</p>
+<h2>Properties</h2>
+<table>
+",
+        escape(code),
+        escape(&remove_duplicate_spaces(&npubegreb.full_definition)),
+        escape(&remove_duplicate_spaces(&npubegreb.short_definition))
+    );
+    let specialty = if npubegreb.specialty.trim().is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<a href=\"../specialty/{}\">{}</a>",
+            group_page(npubegreb.specialty.trim()),
+            escape(npubegreb.specialty.trim())
+        )
+    };
+    let system = if npubegreb.system.trim().is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<a href=\"../system/{}\">{}</a>",
+            group_page(npubegreb.system.trim()),
+            escape(npubegreb.system.trim())
+        )
+    };
+    for (name, value) in [
+        ("Status", status(npubegreb).to_owned()),
+        ("System", system),
+        ("System specification", escape(&npubegreb.sys_spec)),
+        ("Component", escape(&npubegreb.component)),
+        ("Component specification", escape(&npubegreb.comp_spec)),
+        ("Kind of property", escape(&npubegreb.kind_of_property)),
+        ("Procedure", escape(&npubegreb.proc)),
+        ("Unit", escape(&npubegreb.unit)),
+        ("Specialty", specialty),
+        ("Scale type", escape(&npubegreb.scale_type)),
+        ("Group", escape(&npubegreb.group)),
+        ("Context dependent", escape(&npubegreb.context_dependent)),
+        ("Created", escape(&npubegreb.created_date)),
+        ("Changed", escape(&npubegreb.change_date)),
+        ("Effective from", escape(&npubegreb.effective_from)),
+        ("Effective to", escape(&npubegreb.effective_to)),
+        ("Replaces", code_links(&npubegreb.replaces, shown)),
+        ("Replaced by", code_links(&npubegreb.replaced_by, shown)),
+    ] {
+        if !value.trim().is_empty() {
+            body.push_str(&format!("<tr><th>{name}</th><td>{value}</td></tr>\n"));
+        }
+    }
+    body.push_str("</table>\n");
+
+    if !replaced.is_empty() {
+        body.push_str(&format!(
+            "<p>Replacement for {}.</p>\n",
+            code_links(&replaced.join(" "), shown)
+        ));
+    }
+
+    let mut history = versions.to_vec();
+    history.sort_by(|a, b| a.effective_from.cmp(&b.effective_from));
+    body.push_str(
+        "<h2>Version history</h2>
+<table>
+<tr><th>Effective from</th><th>Effective to</th><th>Changed</th><th>Comment</th><th>Short definition</th><th>Status</th></tr>
+",
+    );
+    for version in history {
+        body.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            class(version),
+            escape(&version.effective_from),
+            escape(&version.effective_to),
+            escape(&version.change_date),
+            escape(&version.change_comment),
+            escape(&remove_duplicate_spaces(&version.short_definition)),
+            status(version)
+        ));
+    }
+    body.push_str("</table>\n");
+
+    page(code, "../", &body)
+}
+
+/// Links to the pages of the codes in a `replaces` or `replaced_by` field;
+/// codes that are not in the release are shown without a link.
+fn code_links(field: &str, shown: &BTreeMap<&str, &Npubegreb>) -> String {
+    split_codes(field)
+        .map(|code| {
+            if shown.contains_key(code) {
+                format!("<a href=\"{}\">{}</a>", code_page(code), escape(code))
+            } else {
+                escape(code)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn page(title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{}</title>
+<link rel=\"stylesheet\" href=\"{root}style.css\">
+</head>
+<body>
+{body}</body>
+</html>
+",
+        escape(title)
+    )
+}
+
+fn status(npubegreb: &Npubegreb) -> &'static str {
+    if npubegreb.active == "1" {
+        "active"
+    } else {
+        "retired"
+    }
+}
+
+fn class(npubegreb: &Npubegreb) -> &'static str {
+    if npubegreb.active == "1" {
+        ""
+    } else {
+        " class=\"retired\""
+    }
+}
+
+fn group_name(name: &str) -> &str {
+    if name.is_empty() { "(none)" } else { name }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn code_page(code: &str) -> String {
+    format!("{}.html", file_name(code))
+}
+
+fn group_page(name: &str) -> String {
+    format!("{}.html", file_name(group_name(name)))
+}
+
+/// A file name for `name`, with the characters that are not safe in file
+/// names and URLs replaced by `_`, and a hash suffix if any were replaced so
+/// that different names do not share a page.
+fn file_name(name: &str) -> String {
+    let safe: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if safe == name {
+        return safe;
+    }
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{safe}_{:08x}", hash as u32)
+}
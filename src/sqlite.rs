@@ -0,0 +1,275 @@
+//! SQLite terminology database, for applications that look up NPU codes
+//! offline without parsing the XML.
+
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::code_system::{Loss, remove_duplicate_spaces};
+use crate::error::Error;
+use crate::npu_concepts::{Npubegreber, split_codes};
+
+const SCHEMA: &str = "
+CREATE TABLE concepts (
+    code TEXT PRIMARY KEY,
+    display TEXT,
+    definition TEXT,
+    status TEXT,
+    inactive INTEGER NOT NULL
+);
+CREATE TABLE concept_versions (
+    npu_code TEXT NOT NULL,
+    created_date TEXT,
+    change_date TEXT,
+    change_comment TEXT,
+    short_definition TEXT,
+    system_short TEXT,
+    sys_spec_short TEXT,
+    component_short TEXT,
+    comp_spec_short TEXT,
+    kind_of_property_short TEXT,
+    proc_short TEXT,
+    unit_short TEXT,
+    full_definition TEXT,
+    system TEXT,
+    sys_spec TEXT,
+    component TEXT,
+    comp_spec TEXT,
+    kind_of_property TEXT,
+    proc TEXT,
+    unit TEXT,
+    specialty TEXT,
+    context_dependent TEXT,
+    \"group\" TEXT,
+    scale_type TEXT,
+    replaces TEXT,
+    replaced_by TEXT,
+    effective_from TEXT,
+    effective_to TEXT,
+    active TEXT,
+    current_version TEXT
+);
+CREATE INDEX concept_versions_npu_code ON concept_versions (npu_code);
+CREATE TABLE properties (
+    code TEXT NOT NULL REFERENCES concepts (code),
+    property TEXT NOT NULL,
+    type TEXT NOT NULL,
+    value TEXT
+);
+CREATE INDEX properties_code ON properties (code);
+CREATE INDEX properties_property_value ON properties (property, value);
+CREATE TABLE replacements (
+    code TEXT NOT NULL,
+    replaced_by TEXT NOT NULL,
+    PRIMARY KEY (code, replaced_by)
+);
+CREATE INDEX replacements_replaced_by ON replacements (replaced_by);
+CREATE TABLE designations (
+    code TEXT NOT NULL,
+    use TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE INDEX designations_code ON designations (code);
+CREATE VIRTUAL TABLE concepts_fts USING fts5 (
+    code UNINDEXED,
+    display,
+    definition,
+    content = 'concepts'
+);
+";
+
+/// Write the database to `path`, replacing any existing file.
+///
+/// `concepts`, `properties` and the FTS index mirror the serialized
+/// CodeSystem, while `concept_versions`, `replacements` and `designations`
+/// are taken from all rows of the NPU XML. A code with more than one current
+/// version keeps the first in `concepts`, and the others are added to
+/// `losses`.
+pub fn write<T: Serialize>(
+    path: impl AsRef<Path>,
+    code_system: &T,
+    data: &Npubegreber,
+    losses: &mut Vec<Loss>,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| Error::io(path.display().to_string(), e))?;
+    }
+
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+
+    let code_system = serde_json::to_value(code_system)?;
+    let transaction = connection.transaction()?;
+    {
+        let mut insert_concept = transaction.prepare(
+            "INSERT OR IGNORE INTO concepts (code, display, definition, status, inactive)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_property = transaction.prepare(
+            "INSERT INTO properties (code, property, type, value) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for concept in code_system
+            .get("concept")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let code = concept.get("code").and_then(Value::as_str);
+            let properties: Vec<(&str, &str, &Value)> = concept
+                .get("property")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|property| {
+                    let (key, value) = property
+                        .as_object()?
+                        .iter()
+                        .find(|(key, _)| key.starts_with("value"))?;
+                    let property_code = property.get("code")?.as_str()?;
+                    Some((property_code, key.trim_start_matches("value"), value))
+                })
+                .collect();
+
+            let status = properties
+                .iter()
+                .find(|(property_code, _, _)| *property_code == "status")
+                .and_then(|(_, _, value)| value.as_str());
+            let inactive = properties
+                .iter()
+                .find(|(property_code, _, _)| *property_code == "inactive")
+                .and_then(|(_, _, value)| value.as_bool())
+                .unwrap_or(false);
+            let inserted = insert_concept.execute(params![
+                code,
+                concept.get("display").and_then(Value::as_str),
+                concept.get("definition").and_then(Value::as_str),
+                status,
+                inactive
+            ])?;
+            if inserted == 0 {
+                let code = code.unwrap_or_default();
+                losses.push(Loss::new(
+                    code,
+                    "current_version",
+                    "true",
+                    "duplicate current version, row skipped",
+                ));
+                continue;
+            }
+
+            for (property_code, value_type, value) in properties {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                insert_property.execute(params![code, property_code, value_type, value])?;
+            }
+        }
+
+        let mut insert_version = transaction.prepare(
+            "INSERT INTO concept_versions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+             ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25,
+             ?26, ?27, ?28, ?29, ?30)",
+        )?;
+        let mut insert_replacement = transaction
+            .prepare("INSERT OR IGNORE INTO replacements (code, replaced_by) VALUES (?1, ?2)")?;
+        let mut insert_designation = transaction
+            .prepare("INSERT INTO designations (code, use, value) VALUES (?1, ?2, ?3)")?;
+        for npubegreb in &data.npubegreb {
+            insert_version.execute(params![
+                npubegreb.npu_code,
+                npubegreb.created_date,
+                npubegreb.change_date,
+                npubegreb.change_comment,
+                npubegreb.short_definition,
+                npubegreb.system_short,
+                npubegreb.sys_spec_short,
+                npubegreb.component_short,
+                npubegreb.comp_spec_short,
+                npubegreb.kind_of_property_short,
+                npubegreb.proc_short,
+                npubegreb.unit_short,
+                npubegreb.full_definition,
+                npubegreb.system,
+                npubegreb.sys_spec,
+                npubegreb.component,
+                npubegreb.comp_spec,
+                npubegreb.kind_of_property,
+                npubegreb.proc,
+                npubegreb.unit,
+                npubegreb.specialty,
+                npubegreb.context_dependent,
+                npubegreb.group,
+                npubegreb.scale_type,
+                npubegreb.replaces,
+                npubegreb.replaced_by,
+                npubegreb.effective_from,
+                npubegreb.effective_to,
+                npubegreb.active,
+                npubegreb.current_version,
+            ])?;
+
+            for replaced in split_codes(&npubegreb.replaces) {
+                insert_replacement.execute(params![replaced, npubegreb.npu_code])?;
+            }
+            for replacement in split_codes(&npubegreb.replaced_by) {
+                insert_replacement.execute(params![npubegreb.npu_code, replacement])?;
+            }
+
+            if npubegreb.is_current() {
+                for (designation_use, value) in [
+                    ("short_definition", npubegreb.short_definition.as_str()),
+                    ("full_definition", npubegreb.full_definition.as_str()),
+                ] {
+                    let value = remove_duplicate_spaces(value);
+                    if !value.is_empty() {
+                        insert_designation.execute(params![
+                            npubegreb.npu_code,
+                            designation_use,
+                            value
+                        ])?;
+                    }
+                }
+            }
+        }
+    }
+    transaction.execute_batch("INSERT INTO concepts_fts (concepts_fts) VALUES ('rebuild');")?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    #[test]
+    fn duplicate_current_rows_are_skipped() {
+        let path = std::env::temp_dir().join(format!("npu-sqlite-{}.db", std::process::id()));
+        let code_system = serde_json::json!({
+            "concept": [
+                { "code": "NPU01006", "display": "first" },
+                { "code": "NPU01006", "display": "second" },
+            ]
+        });
+        let mut losses = Vec::new();
+        write(&path, &code_system, &test_data(), &mut losses).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let display: String = connection
+            .query_row(
+                "SELECT display FROM concepts WHERE code = 'NPU01006'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(display, "first");
+        assert_eq!(losses.len(), 1);
+        assert_eq!(losses[0].npu_code, "NPU01006");
+    }
+}
@@ -0,0 +1,102 @@
+//! CSV and TSV export of the serialized CodeSystem concepts, with one column
+//! per declared `CodeSystem.property`, so the exact FHIR content can be
+//! reviewed in a spreadsheet.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// RFC 4180 CSV; fields containing a comma, quote or line break are quoted.
+pub fn csv<T: Serialize>(code_system: &T) -> Result<String, serde_json::Error> {
+    Ok(write_table(&table(code_system)?, ',', |field| {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }))
+}
+
+/// Tab separated values; tabs and line breaks in fields are replaced by spaces.
+pub fn tsv<T: Serialize>(code_system: &T) -> Result<String, serde_json::Error> {
+    Ok(write_table(&table(code_system)?, '\t', |field| {
+        field.replace(['\t', '\n', '\r'], " ")
+    }))
+}
+
+fn write_table(rows: &[Vec<String>], delimiter: char, escape: impl Fn(&str) -> String) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (i, field) in row.iter().enumerate() {
+            if i > 0 {
+                out.push(delimiter);
+            }
+            out.push_str(&escape(field));
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// The header row followed by one row per concept.
+fn table<T: Serialize>(code_system: &T) -> Result<Vec<Vec<String>>, serde_json::Error> {
+    let value = serde_json::to_value(code_system)?;
+
+    let properties: Vec<&str> = value
+        .get("property")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|property| property.get("code").and_then(Value::as_str))
+        .collect();
+
+    let mut header = vec![
+        "code".to_owned(),
+        "display".to_owned(),
+        "definition".to_owned(),
+    ];
+    header.extend(properties.iter().map(|&code| code.to_owned()));
+    let mut rows = vec![header];
+
+    for concept in value
+        .get("concept")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let mut row: Vec<String> = ["code", "display", "definition"]
+            .iter()
+            .map(|key| concept.get(*key).map(value_to_string).unwrap_or_default())
+            .collect();
+        for code in &properties {
+            let values: Vec<String> = concept
+                .get("property")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter(|property| property.get("code").and_then(Value::as_str) == Some(code))
+                .filter_map(property_value)
+                .collect();
+            row.push(values.join(" | "));
+        }
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// The `value[x]` of a `CodeSystem.concept.property`.
+fn property_value(property: &Value) -> Option<String> {
+    property
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.starts_with("value"))
+        .map(|(_, value)| value_to_string(value))
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
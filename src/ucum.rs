@@ -0,0 +1,185 @@
+//! Conversion of the NPU unit names, e.g. `micromole per litre` or
+//! `10<sup>9</sup> per litre`, to UCUM codes such as `umol/L` and `10*9/L`.
+
+/// The UCUM code system.
+pub const SYSTEM: &str = "http://unitsofmeasure.org";
+
+const PREFIXES: [(&str, &str); 12] = [
+    ("atto", "a"),
+    ("femto", "f"),
+    ("pico", "p"),
+    ("nano", "n"),
+    ("micro", "u"),
+    ("milli", "m"),
+    ("centi", "c"),
+    ("deci", "d"),
+    ("kilo", "k"),
+    ("mega", "M"),
+    ("giga", "G"),
+    ("tera", "T"),
+];
+
+const UNITS: [(&str, &str); 22] = [
+    ("mole", "mol"),
+    ("litre", "L"),
+    ("liter", "L"),
+    ("gram", "g"),
+    ("katal", "kat"),
+    ("metre", "m"),
+    ("meter", "m"),
+    ("second", "s"),
+    ("minute", "min"),
+    ("hour", "h"),
+    ("day", "d"),
+    ("week", "wk"),
+    ("year", "a"),
+    ("pascal", "Pa"),
+    ("osmole", "osm"),
+    ("degree Celsius", "Cel"),
+    ("international unit", "[IU]"),
+    ("arbitrary unit", "[arb'U]"),
+    ("enzyme unit", "U"),
+    ("unit", "U"),
+    ("percent", "%"),
+    ("one", "1"),
+];
+
+/// The UCUM code of the NPU `unit` name, or `None` if the name is empty or
+/// not understood.
+pub fn from_npu(unit: &str) -> Option<String> {
+    let unit = unit.split_whitespace().collect::<Vec<_>>().join(" ");
+    if unit.is_empty() {
+        return None;
+    }
+    // A unit such as `per litre` has an empty numerator, giving `/L`.
+    let (numerator, rest) = match unit.strip_prefix("per ") {
+        Some(rest) => (String::new(), rest),
+        None => {
+            let (numerator, rest) = unit.split_once(" per ").unwrap_or((&unit, ""));
+            (factor_code(numerator)?, rest)
+        }
+    };
+    let mut code = numerator;
+    if !rest.is_empty() {
+        for denominator in rest.split(" per ") {
+            code.push('/');
+            code.push_str(&factor_code(denominator)?);
+        }
+    }
+    Some(if code == "10*-2" {
+        "%".to_owned()
+    } else {
+        code
+    })
+}
+
+/// The code of one side of a quotient, e.g. `10<sup>9</sup>` or `square metre`.
+fn factor_code(factor: &str) -> Option<String> {
+    let factor = factor.trim_start_matches('×').trim_start();
+    if let Some(rest) = factor.strip_prefix("10<sup>") {
+        let (exponent, rest) = rest.split_once("</sup>")?;
+        exponent.trim().parse::<i32>().ok()?;
+        let power = format!("10*{}", exponent.trim());
+        let rest = rest.trim();
+        return Some(if rest.is_empty() {
+            power
+        } else {
+            format!("{power}.{}", factor_code(rest)?)
+        });
+    }
+    for (name, exponent) in [("square ", "2"), ("cubic ", "3")] {
+        if let Some(rest) = factor.strip_prefix(name) {
+            return Some(format!("{}{exponent}", unit_code(rest)?));
+        }
+    }
+    unit_code(factor)
+}
+
+/// The code of a unit with an optional prefix, e.g. `micromole` or
+/// `milli-international units`.
+fn unit_code(name: &str) -> Option<String> {
+    if let Some(code) = base_unit(name) {
+        return Some(code.to_owned());
+    }
+    PREFIXES.iter().find_map(|(prefix, symbol)| {
+        let rest = name.strip_prefix(prefix)?;
+        let code = base_unit(rest.strip_prefix(['-', ' ']).unwrap_or(rest))?;
+        Some(format!("{symbol}{code}"))
+    })
+}
+
+fn base_unit(name: &str) -> Option<&'static str> {
+    let lookup = |name: &str| {
+        UNITS
+            .iter()
+            .find(|(unit, _)| unit.eq_ignore_ascii_case(name))
+            .map(|(_, code)| *code)
+    };
+    lookup(name).or_else(|| lookup(name.strip_suffix('s')?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npu_concepts::test_data;
+
+    #[test]
+    fn test_data_units() {
+        for npubegreb in test_data().npubegreb {
+            let code = from_npu(&npubegreb.unit);
+            match npubegreb.unit.trim() {
+                "" => assert_eq!(code, None),
+                "micromole per litre" => assert_eq!(code.as_deref(), Some("umol/L")),
+                "millimole per litre" => assert_eq!(code.as_deref(), Some("mmol/L")),
+                "nanomole per litre" => assert_eq!(code.as_deref(), Some("nmol/L")),
+                "microkatal per litre" => assert_eq!(code.as_deref(), Some("ukat/L")),
+                "10<sup>-2</sup>" => assert_eq!(code.as_deref(), Some("%")),
+                unit => panic!("{}: unexpected unit {unit:?}", npubegreb.npu_code),
+            }
+        }
+    }
+
+    #[test]
+    fn powers_of_ten() {
+        assert_eq!(
+            from_npu("10<sup>9</sup> per litre").as_deref(),
+            Some("10*9/L")
+        );
+        assert_eq!(from_npu("10<sup>-3</sup>").as_deref(), Some("10*-3"));
+        assert_eq!(
+            from_npu("10<sup>6</sup> cells per litre"),
+            None,
+            "cells is not a unit"
+        );
+        assert_eq!(
+            from_npu("× 10<sup>12</sup> per litre").as_deref(),
+            Some("10*12/L")
+        );
+        assert_eq!(from_npu("10<sup>x</sup> per litre"), None);
+        assert_eq!(from_npu("10<sup>9 per litre"), None);
+    }
+
+    #[test]
+    fn prefixes_and_powers() {
+        assert_eq!(from_npu("square metre").as_deref(), Some("m2"));
+        assert_eq!(from_npu("cubic millimetre").as_deref(), Some("mm3"));
+        assert_eq!(
+            from_npu("milli-international units per litre").as_deref(),
+            Some("m[IU]/L")
+        );
+        assert_eq!(from_npu("kilopascal").as_deref(), Some("kPa"));
+        assert_eq!(from_npu("degree Celsius").as_deref(), Some("Cel"));
+    }
+
+    #[test]
+    fn quotients() {
+        assert_eq!(from_npu("per litre").as_deref(), Some("/L"));
+        assert_eq!(
+            from_npu("milligram per kilogram per day").as_deref(),
+            Some("mg/kg/d")
+        );
+        assert_eq!(from_npu("  mole   per  litre ").as_deref(), Some("mol/L"));
+        assert_eq!(from_npu("mole per furlong"), None);
+        assert_eq!(from_npu("   "), None);
+    }
+}
@@ -0,0 +1,420 @@
+//! Validation of the generated CodeSystem against the FHIR invariants that
+//! the builders do not enforce, reported as an `OperationOutcome`.
+
+use std::collections::{HashMap, HashSet};
+
+use fhir_sdk::r5::codes;
+use fhir_sdk::r5::resources::{OperationOutcome, OperationOutcomeIssue};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::check::Severity;
+use crate::error::{BuildContext, Error};
+
+#[derive(Clone, Copy)]
+pub enum IssueKind {
+    Duplicate,
+    Invariant,
+    Value,
+}
+
+pub struct Issue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    pub diagnostics: String,
+    /// FHIRPath to the offending element.
+    pub expression: String,
+}
+
+impl Issue {
+    fn error(kind: IssueKind, expression: String, diagnostics: String) -> Self {
+        Issue {
+            severity: Severity::Error,
+            kind,
+            diagnostics,
+            expression,
+        }
+    }
+}
+
+/// Validate the serialized `code_system`.
+pub fn validate<T: Serialize>(code_system: &T) -> Result<Vec<Issue>, serde_json::Error> {
+    let code_system = serde_json::to_value(code_system)?;
+    let mut issues = Vec::new();
+
+    // Declared properties and their value[x] names.
+    let mut declared = HashMap::<&str, &str>::new();
+    for (i, property) in array(&code_system, "property").enumerate() {
+        let code = property
+            .get("code")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let r#type = property
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if declared.insert(code, r#type).is_some() {
+            issues.push(Issue::error(
+                IssueKind::Duplicate,
+                format!("CodeSystem.property[{i}]"),
+                format!("property code {code} is declared more than once"),
+            ));
+        }
+    }
+
+    let mut seen = HashSet::<&str>::new();
+    let mut total = 0;
+    validate_concepts(
+        &code_system,
+        "CodeSystem",
+        &declared,
+        &mut seen,
+        &mut total,
+        &mut issues,
+    );
+
+    let content = code_system.get("content").and_then(Value::as_str);
+    let count = code_system.get("count").and_then(Value::as_u64);
+    if content == Some("complete") && count != Some(total) {
+        issues.push(Issue::error(
+            IssueKind::Invariant,
+            "CodeSystem.count".to_owned(),
+            format!(
+                "count is {} but the code system contains {total} concepts",
+                count.map_or("missing".to_owned(), |count| count.to_string())
+            ),
+        ));
+    }
+
+    if let Some(div) = code_system
+        .get("text")
+        .and_then(|text| text.get("div"))
+        .and_then(Value::as_str)
+        && let Err(message) = check_xhtml(div)
+    {
+        issues.push(Issue::error(
+            IssueKind::Invariant,
+            "CodeSystem.text.div".to_owned(),
+            format!("narrative is not valid XHTML: {message}"),
+        ));
+    }
+
+    Ok(issues)
+}
+
+/// Build the `OperationOutcome` for `issues`, with a single informational
+/// issue if there are none.
+pub fn operation_outcome(issues: &[Issue]) -> Result<OperationOutcome, Error> {
+    let mut outcome_issues = Vec::new();
+    for issue in issues {
+        outcome_issues.push(Some(
+            OperationOutcomeIssue::builder()
+                .severity(match issue.severity {
+                    Severity::Error => codes::IssueSeverity::Error,
+                    Severity::Warning => codes::IssueSeverity::Warning,
+                })
+                .code(match issue.kind {
+                    IssueKind::Duplicate => codes::IssueType::Duplicate,
+                    IssueKind::Invariant => codes::IssueType::Invariant,
+                    IssueKind::Value => codes::IssueType::Value,
+                })
+                .diagnostics(issue.diagnostics.clone())
+                .expression(vec![Some(issue.expression.clone())])
+                .build()
+                .context("OperationOutcomeIssue", None, None)?,
+        ));
+    }
+    if outcome_issues.is_empty() {
+        outcome_issues.push(Some(
+            OperationOutcomeIssue::builder()
+                .severity(codes::IssueSeverity::Information)
+                .code(codes::IssueType::Informational)
+                .diagnostics("No issues found".to_owned())
+                .build()
+                .context("OperationOutcomeIssue", None, None)?,
+        ));
+    }
+
+    OperationOutcome::builder()
+        .issue(outcome_issues)
+        .build()
+        .context("OperationOutcome", None, None)
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn validate_concepts<'a>(
+    parent: &'a Value,
+    path: &str,
+    declared: &HashMap<&str, &str>,
+    seen: &mut HashSet<&'a str>,
+    total: &mut u64,
+    issues: &mut Vec<Issue>,
+) {
+    for (i, concept) in array(parent, "concept").enumerate() {
+        let concept_path = format!("{path}.concept[{i}]");
+        *total += 1;
+
+        let code = concept
+            .get("code")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if !seen.insert(code) {
+            issues.push(Issue::error(
+                IssueKind::Duplicate,
+                concept_path.clone(),
+                format!("code {code} is not unique"),
+            ));
+        }
+
+        for (j, property) in array(concept, "property").enumerate() {
+            let property_path = format!("{concept_path}.property[{j}]");
+            let property_code = property
+                .get("code")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let Some(r#type) = declared.get(property_code) else {
+                issues.push(Issue::error(
+                    IssueKind::Invariant,
+                    property_path,
+                    format!(
+                        "{code}: property {property_code} is not declared in CodeSystem.property"
+                    ),
+                ));
+                continue;
+            };
+            let expected = value_name(r#type);
+            let actual = property
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(key, _)| key.as_str())
+                .find(|key| key.starts_with("value"));
+            if actual != Some(expected.as_str()) {
+                issues.push(Issue::error(
+                    IssueKind::Value,
+                    property_path,
+                    format!(
+                        "{code}: property {property_code} is declared as {type} but has {}",
+                        actual.unwrap_or("no value")
+                    ),
+                ));
+            }
+        }
+
+        validate_concepts(concept, &concept_path, declared, seen, total, issues);
+    }
+}
+
+/// The `value[x]` element name for a `PropertyType` code, e.g. `valueDateTime`
+/// for `dateTime`.
+fn value_name(r#type: &str) -> String {
+    let mut chars = r#type.chars();
+    match chars.next() {
+        Some(first) => format!("value{}{}", first.to_ascii_uppercase(), chars.as_str()),
+        None => "value".to_owned(),
+    }
+}
+
+/// Elements that FHIR narrative must not contain (txt-1).
+const FORBIDDEN_ELEMENTS: &[&str] = &[
+    "script", "style", "form", "input", "button", "select", "textarea", "iframe", "object",
+    "embed", "applet", "base", "link", "meta", "frame", "frameset",
+];
+
+/// Check that `div` is a well-formed XHTML `div` element with content and
+/// without the elements and attributes FHIR forbids in narrative.
+fn check_xhtml(div: &str) -> Result<(), String> {
+    let mut stack = Vec::<&str>::new();
+    let mut root_closed = false;
+    let mut has_content = false;
+    let mut rest = div.trim();
+
+    while !rest.is_empty() {
+        if root_closed {
+            return Err("content after the root div".to_owned());
+        }
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").ok_or("unterminated comment")?;
+            rest = &comment[end + 3..];
+        } else if let Some(tag) = rest.strip_prefix("</") {
+            let end = tag.find('>').ok_or("unterminated end tag")?;
+            let name = tag[..end].trim();
+            match stack.pop() {
+                Some(open) if open == name => {}
+                Some(open) => return Err(format!("</{name}> closes <{open}>")),
+                None => return Err(format!("</{name}> without start tag")),
+            }
+            root_closed = stack.is_empty();
+            rest = tag[end + 1..].trim_start();
+        } else if let Some(tag) = rest.strip_prefix('<') {
+            let end = tag.find('>').ok_or("unterminated start tag")?;
+            let (content, self_closing) = match tag[..end].strip_suffix('/') {
+                Some(content) => (content, true),
+                None => (&tag[..end], false),
+            };
+            let (name, attributes) = content
+                .split_once(char::is_whitespace)
+                .unwrap_or((content, ""));
+            let root = stack.is_empty();
+            if root && name != "div" {
+                return Err("the root element is not a div".to_owned());
+            }
+            if FORBIDDEN_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+                return Err(format!("<{name}> is not allowed in narrative"));
+            }
+            check_attributes(name, attributes, root)?;
+            if self_closing {
+                root_closed = root;
+            } else {
+                stack.push(name);
+            }
+            rest = &tag[end + 1..];
+        } else if stack.is_empty() {
+            return Err("text outside the root div".to_owned());
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            check_entities(text)?;
+            if !text.trim().is_empty() {
+                has_content = true;
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    if let Some(open) = stack.pop() {
+        return Err(format!("<{open}> is not closed"));
+    }
+    if !has_content {
+        return Err("the narrative has no text content".to_owned());
+    }
+    Ok(())
+}
+
+fn check_attributes(element: &str, attributes: &str, root: bool) -> Result<(), String> {
+    let mut rest = attributes.trim();
+    let mut namespace = None;
+    while !rest.is_empty() {
+        let (name, value) = rest
+            .split_once('=')
+            .ok_or_else(|| format!("malformed attributes in <{element}>"))?;
+        let name = name.trim();
+        let value = value.trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("unquoted attribute {name} in <{element}>"))?;
+        let end = value[1..]
+            .find(quote)
+            .ok_or_else(|| format!("unterminated attribute {name} in <{element}>"))?;
+        let attribute_value = &value[1..end + 1];
+        check_entities(attribute_value)?;
+        if name.to_ascii_lowercase().starts_with("on") {
+            return Err(format!(
+                "event attribute {name} is not allowed in narrative"
+            ));
+        }
+        if name == "xmlns" {
+            namespace = Some(attribute_value);
+        }
+        rest = value[end + 2..].trim_start();
+    }
+    if root && namespace != Some("http://www.w3.org/1999/xhtml") {
+        return Err("the root div is not in the XHTML namespace".to_owned());
+    }
+    Ok(())
+}
+
+fn check_entities(text: &str) -> Result<(), String> {
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        let entity = &rest[start + 1..];
+        let end = entity
+            .find(';')
+            .ok_or_else(|| "unterminated entity reference".to_owned())?;
+        let name = &entity[..end];
+        let valid = match name.strip_prefix('#') {
+            Some(number) => match number.strip_prefix(['x', 'X']) {
+                Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+                None => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+            },
+            None => ["amp", "lt", "gt", "quot", "apos"].contains(&name),
+        };
+        if !valid {
+            return Err(format!("invalid entity reference &{name};"));
+        }
+        rest = &entity[end + 1..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XHTML: &str = "xmlns=\"http://www.w3.org/1999/xhtml\"";
+
+    fn div(content: &str) -> String {
+        format!("<div {XHTML}>{content}</div>")
+    }
+
+    #[test]
+    fn well_formed_narrative_is_accepted() {
+        for content in [
+            "<p>Sodium &amp; potassium</p>",
+            "<table class=\"grid\"><tr><td>&#181;mol/L &#xB5;</td></tr></table>",
+            "<!-- generated --><p>text<br/>more</p>",
+            "<p title='a &quot;b&quot;'>text</p>",
+        ] {
+            assert_eq!(check_xhtml(&div(content)), Ok(()), "{content}");
+        }
+    }
+
+    #[test]
+    fn malformed_narrative_is_rejected() {
+        for (xhtml, error) in [
+            ("<p>text</p>".to_owned(), "the root element is not a div"),
+            (
+                "<div><p>text</p></div>".to_owned(),
+                "the root div is not in the XHTML namespace",
+            ),
+            ("text".to_owned(), "text outside the root div"),
+            (
+                format!("{}<p>after</p>", div("<p>text</p>")),
+                "content after the root div",
+            ),
+            (div("<p>text</b>"), "</b> closes <p>"),
+            (format!("<div {XHTML}><p>text</p>"), "<div> is not closed"),
+            (div("<p></p>"), "the narrative has no text content"),
+            (div("<p>a & b</p>"), "unterminated entity reference"),
+            (div("<p>&nbsp;</p>"), "invalid entity reference &nbsp;"),
+            (div("<p>text<!-- open</p>"), "unterminated comment"),
+            (
+                div("<p class=grid>text</p>"),
+                "unquoted attribute class in <p>",
+            ),
+        ] {
+            assert_eq!(check_xhtml(&xhtml), Err(error.to_owned()), "{xhtml}");
+        }
+    }
+
+    #[test]
+    fn active_content_is_rejected() {
+        assert_eq!(
+            check_xhtml(&div("<p>text</p><script>alert(1)</script>")),
+            Err("<script> is not allowed in narrative".to_owned())
+        );
+        assert_eq!(
+            check_xhtml(&div("<p onclick=\"alert(1)\">text</p>")),
+            Err("event attribute onclick is not allowed in narrative".to_owned())
+        );
+    }
+}
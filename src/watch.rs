@@ -0,0 +1,60 @@
+//! Polling of the input files for `--watch`, by modification time so that it
+//! needs no platform file notification API.
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modification times of a set of files, `None` for files that cannot be
+/// read, e.g. while an editor replaces them.
+pub struct Watcher {
+    paths: Vec<String>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl Watcher {
+    pub fn new(paths: Vec<String>) -> Self {
+        let modified = modified(&paths);
+        Watcher { paths, modified }
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Block until a file changes and the modification times have then been
+    /// stable for one poll interval, so that a file being written is not read
+    /// half-way.
+    pub fn wait(&mut self) {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = modified(&self.paths);
+            if current != self.modified {
+                self.modified = current;
+                break;
+            }
+        }
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = modified(&self.paths);
+            if current == self.modified {
+                return;
+            }
+            self.modified = current;
+        }
+    }
+}
+
+fn modified(paths: &[String]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
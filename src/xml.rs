@@ -0,0 +1,21 @@
+//! Escaping of text for the XML and HTML outputs.
+
+/// `s` with the characters that are special in XML and HTML text and
+/// attribute values replaced by character references. Line breaks and tabs
+/// are escaped too, so that attribute value normalization keeps them.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            '\t' => escaped.push_str("&#x9;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}